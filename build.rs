@@ -0,0 +1,64 @@
+//! Collects the numbered `NN-name.up.sql`/`NN-name.down.sql` migrations in
+//! `src/sql` into `$OUT_DIR/migrations.rs`, included by `crate::sql`.
+
+use std::{collections::BTreeMap, env, fs, path::Path};
+
+fn main() {
+    let dir = Path::new(&env::var("CARGO_MANIFEST_DIR").unwrap()).join("src/sql");
+    println!("cargo:rerun-if-changed={}", dir.display());
+
+    // number -> (name, up, down)
+    let mut migrations: BTreeMap<usize, (String, Option<String>, Option<String>)> = BTreeMap::new();
+    for entry in fs::read_dir(&dir).expect("read src/sql") {
+        let path = entry.unwrap().path();
+        let file_name = path.file_name().unwrap().to_str().unwrap().to_owned();
+        let (stem, is_up) = if let Some(stem) = file_name.strip_suffix(".up.sql") {
+            (stem, true)
+        } else if let Some(stem) = file_name.strip_suffix(".down.sql") {
+            (stem, false)
+        } else {
+            continue;
+        };
+        let (number, name) = stem
+            .split_once('-')
+            .unwrap_or_else(|| panic!("migration `{}` is not named `NN-name`", file_name));
+        let number: usize = number
+            .parse()
+            .unwrap_or_else(|_| panic!("migration `{}` is not numbered", file_name));
+        let entry = migrations
+            .entry(number)
+            .or_insert_with(|| (name.to_owned(), None, None));
+        if entry.0 != name {
+            panic!(
+                "migration {} has two names: `{}` and `{}`",
+                number, entry.0, name
+            );
+        }
+        let slot = if is_up { &mut entry.1 } else { &mut entry.2 };
+        *slot = Some(path.display().to_string());
+    }
+
+    let mut out = String::from("pub const MIGRATION_FILES: &[MigrationFile] = &[\n");
+    for (expected, (number, (name, up, down))) in migrations.iter().enumerate() {
+        if expected != *number {
+            panic!("migration {} is missing", expected);
+        }
+        let up = up
+            .as_ref()
+            .unwrap_or_else(|| panic!("migration {} has no `.up.sql`", number));
+        let down = match down {
+            Some(down) => format!("Some(include_str!({:?}))", down),
+            None => "None".to_owned(),
+        };
+        out.push_str(&format!(
+            "    MigrationFile {{ number: {}, name: {:?}, up: include_str!({:?}), down: {} }},\n",
+            number, name, up, down
+        ));
+    }
+    out.push_str("];\n");
+    fs::write(
+        Path::new(&env::var("OUT_DIR").unwrap()).join("migrations.rs"),
+        out,
+    )
+    .unwrap();
+}
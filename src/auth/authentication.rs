@@ -1,8 +1,11 @@
-use argon2::{Argon2, PasswordHash, PasswordVerifier};
-use color_eyre::eyre::WrapErr;
+use argon2::{
+    password_hash::{rand_core::OsRng, SaltString},
+    Algorithm, Argon2, Params, PasswordHash, PasswordHasher, PasswordVerifier, Version,
+};
 
-use crate::configuration::SQLite3Settings;
-use rusqlite::OptionalExtension;
+use crate::configuration::PasswordHashSettings;
+use crate::model::database::Database;
+use rusqlite::{params, OptionalExtension};
 use secrecy::{ExposeSecret, SecretString};
 use serde::Deserialize;
 use thiserror::Error;
@@ -27,34 +30,104 @@ pub enum LoginError {
 type Result<T, E = LoginError> = std::result::Result<T, E>;
 
 impl LoginCredential {
-    pub async fn validate(&self, db: &SQLite3Settings) -> Result<Option<i64>> {
+    /// Returns the user's ID if the password is correct, upgrading its hash
+    /// to `settings` if needed
+    pub async fn validate(
+        &self,
+        db: &Database,
+        settings: PasswordHashSettings,
+    ) -> Result<Option<i64>> {
         let username = self.username.clone();
-        let conn = db.connect()?;
-        let cred = conn
-            .query_row(
-                r#"SELECT id, phc FROM users WHERE username = ?"#,
-                [username],
-                |row| Ok((row.get(0)?, row.get(1)?)),
-            )
-            .optional()?;
-        if let Some((user_id, phc)) = cred {
-            let password = self.password.clone();
-            let pass = spawn_blocking_with_tracing(move || {
-                verify_password_hash(SecretString::new(phc), password)
+        let cred: Option<(i64, String)> = db
+            .interact(move |conn| {
+                conn.query_row(
+                    r#"SELECT id, phc FROM users WHERE username = ? AND phc IS NOT NULL"#,
+                    [username],
+                    |row| Ok((row.get(0)?, row.get(1)?)),
+                )
+                .optional()
             })
             .await
             .map_err(|_| LoginError::InternalError)??;
-            if pass {
-                Ok(Some(user_id))
-            } else {
-                Ok(None)
+        let Some((user_id, phc)) = cred else {
+            return Ok(None);
+        };
+        if !verify(phc.clone(), self.password.clone()).await? {
+            return Ok(None);
+        }
+        if needs_rehash(&settings, &phc) {
+            // The login succeeds anyway, the upgrade is retried on the next one
+            if let Err(e) = rehash(db, settings, user_id, phc, self.password.clone()).await {
+                tracing::warn!(
+                    "failed to upgrade the password hash of user {}: {}",
+                    user_id,
+                    e
+                );
             }
-        } else {
-            Ok(None)
         }
+        Ok(Some(user_id))
+    }
+}
+
+/// Replaces the hash of a verified password, unless it changed meanwhile
+#[instrument(skip_all, fields(user_id=user_id))]
+async fn rehash(
+    db: &Database,
+    settings: PasswordHashSettings,
+    user_id: i64,
+    old_phc: String,
+    password: SecretString,
+) -> Result<()> {
+    let phc = spawn_blocking_with_tracing(move || compute_password_hash(&settings, password))
+        .await
+        .map_err(|_| LoginError::InternalError)?
+        .map_err(|_| LoginError::InternalError)?;
+    let updated = db
+        .interact(move |conn| {
+            conn.execute(
+                r#"UPDATE users SET phc = ? WHERE id = ? AND phc = ?"#,
+                params![phc.expose_secret(), user_id, old_phc],
+            )
+        })
+        .await
+        .map_err(|_| LoginError::InternalError)??;
+    if updated > 0 {
+        tracing::info!("upgraded the password hash of user {}", user_id);
     }
+    Ok(())
 }
 
+/// Checks the password of an existing user, e.g. before changing it
+pub async fn verify_user_password(
+    db: &Database,
+    user_id: i64,
+    password: SecretString,
+) -> Result<bool> {
+    let phc = db
+        .interact(move |conn| {
+            conn.query_row(
+                r#"SELECT phc FROM users WHERE id = ? AND phc IS NOT NULL"#,
+                [user_id],
+                |row| row.get(0),
+            )
+            .optional()
+        })
+        .await
+        .map_err(|_| LoginError::InternalError)??;
+    match phc {
+        Some(phc) => verify(phc, password).await,
+        None => Ok(false),
+    }
+}
+
+async fn verify(phc: String, password: SecretString) -> Result<bool> {
+    spawn_blocking_with_tracing(move || verify_password_hash(SecretString::new(phc), password))
+        .await
+        .map_err(|_| LoginError::InternalError)?
+}
+
+/// The algorithm and parameters are read from `phc`, so that hashes computed
+/// with older settings still verify
 #[instrument(skip_all)]
 fn verify_password_hash(phc: SecretString, password: SecretString) -> Result<bool> {
     let hash = PasswordHash::new(phc.expose_secret()).map_err(|_| LoginError::InternalError)?;
@@ -62,3 +135,140 @@ fn verify_password_hash(phc: SecretString, password: SecretString) -> Result<boo
         .verify_password(password.expose_secret().as_bytes(), &hash)
         .is_ok())
 }
+
+/// Whether `phc` was computed with another algorithm or version than
+/// `settings`, or with weaker parameters
+pub fn needs_rehash(settings: &PasswordHashSettings, phc: &str) -> bool {
+    let Ok(hash) = PasswordHash::new(phc) else {
+        return true;
+    };
+    let (Ok(algorithm), Ok(params)) =
+        (Algorithm::try_from(hash.algorithm), Params::try_from(&hash))
+    else {
+        return true;
+    };
+    algorithm != settings.algorithm.into()
+        || hash.version != Some(Version::V0x13.into())
+        || params.m_cost() < settings.memory_kib
+        || params.t_cost() < settings.iterations
+        || params.p_cost() < settings.parallelism
+}
+
+#[instrument(skip_all)]
+pub fn compute_password_hash(
+    settings: &PasswordHashSettings,
+    password: SecretString,
+) -> Result<SecretString, argon2::password_hash::Error> {
+    let salt = SaltString::generate(&mut OsRng);
+    let phc = Argon2::new(
+        settings.algorithm.into(),
+        Version::V0x13,
+        settings.params()?,
+    )
+    .hash_password(password.expose_secret().as_bytes(), &salt)?
+    .to_string();
+    Ok(SecretString::new(phc))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::configuration::HashAlgorithm;
+
+    /// Small costs, so that hashing stays fast
+    fn settings() -> PasswordHashSettings {
+        PasswordHashSettings {
+            algorithm: HashAlgorithm::Argon2id,
+            memory_kib: 64,
+            iterations: 2,
+            parallelism: 2,
+        }
+    }
+
+    fn phc(settings: PasswordHashSettings) -> String {
+        compute_password_hash(&settings, SecretString::new("password".to_owned()))
+            .unwrap()
+            .expose_secret()
+            .clone()
+    }
+
+    #[test]
+    fn hash_at_the_configured_parameters_is_kept() {
+        assert!(!needs_rehash(&settings(), &phc(settings())));
+    }
+
+    #[test]
+    fn stronger_hash_is_kept() {
+        let stronger = PasswordHashSettings {
+            memory_kib: 128,
+            iterations: 3,
+            parallelism: 4,
+            ..settings()
+        };
+        assert!(!needs_rehash(&settings(), &phc(stronger)));
+    }
+
+    #[test]
+    fn weaker_hash_is_upgraded() {
+        let weaker = [
+            PasswordHashSettings {
+                memory_kib: 32,
+                ..settings()
+            },
+            PasswordHashSettings {
+                iterations: 1,
+                ..settings()
+            },
+            PasswordHashSettings {
+                parallelism: 1,
+                ..settings()
+            },
+        ];
+        for weaker in weaker {
+            assert!(needs_rehash(&settings(), &phc(weaker)), "{:?}", weaker);
+        }
+    }
+
+    #[test]
+    fn other_algorithm_is_upgraded() {
+        for algorithm in [HashAlgorithm::Argon2i, HashAlgorithm::Argon2d] {
+            let other = PasswordHashSettings {
+                algorithm,
+                ..settings()
+            };
+            assert!(needs_rehash(&settings(), &phc(other)), "{:?}", algorithm);
+        }
+    }
+
+    #[test]
+    fn other_version_is_upgraded() {
+        let salt = SaltString::generate(&mut OsRng);
+        let phc = Argon2::new(
+            Algorithm::Argon2id,
+            Version::V0x10,
+            settings().params().unwrap(),
+        )
+        .hash_password(b"password", &salt)
+        .unwrap()
+        .to_string();
+        assert!(needs_rehash(&settings(), &phc));
+    }
+
+    #[test]
+    fn seeded_admin_hash_is_upgraded() {
+        let seeded = "$argon2i$v=19$m=16,t=2,p=1$ZHdMaHdYeE1JZ3d6dmo0WQ$SWvpjaTUlShdvYL6qKARQg";
+        assert!(needs_rehash(&PasswordHashSettings::default(), seeded));
+    }
+
+    #[test]
+    fn unparsable_hash_is_upgraded() {
+        for phc in [
+            "",
+            "password",
+            "$argon2id$v=19$m=oops",
+            "$pbkdf2-sha256$i=1$c2FsdA$aGFzaA",
+        ] {
+            assert!(needs_rehash(&settings(), phc), "{:?}", phc);
+        }
+    }
+}
@@ -0,0 +1,172 @@
+use async_trait::async_trait;
+use axum::body::{Bytes, HttpBody};
+use axum::extract::{FromRequest, FromRequestParts};
+use axum::http::request::Parts;
+use axum::http::{Request, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::BoxError;
+use axum_sessions::SessionHandle;
+use maud::{html, Markup, Render};
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use thiserror::Error;
+
+/// Session key of the synchronizer token
+const SESSION_KEY: &str = "csrf_token";
+
+/// Name of the hidden form field carrying the token
+const FIELD_NAME: &str = "csrf_token";
+
+#[derive(Error, Debug)]
+pub enum CsrfError {
+    #[error("missing or invalid CSRF token")]
+    Invalid,
+    #[error("session layer missing")]
+    NoSession,
+    #[error(transparent)]
+    SessionError(#[from] serde_json::Error),
+    #[error("failed to read the request body")]
+    Body,
+    #[error(transparent)]
+    Form(#[from] serde_urlencoded::de::Error),
+}
+
+impl IntoResponse for CsrfError {
+    fn into_response(self) -> Response {
+        match self {
+            CsrfError::Invalid => (
+                StatusCode::FORBIDDEN,
+                "403 Forbidden: the form has expired, go back, reload the page and try again",
+            ),
+            CsrfError::NoSession | CsrfError::SessionError(_) => (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "500 Internal Server Error",
+            ),
+            CsrfError::Body => (StatusCode::BAD_REQUEST, "400 bad request"),
+            CsrfError::Form(_) => (StatusCode::UNPROCESSABLE_ENTITY, "422 unprocessable entity"),
+        }
+        .into_response()
+    }
+}
+
+/// The token of the current session, created on first use. Renders as the
+/// hidden field that every `method="post"` form must include.
+#[derive(Clone, Debug)]
+pub struct CsrfToken(String);
+
+#[async_trait]
+impl<S> FromRequestParts<S> for CsrfToken
+where
+    S: Send + Sync,
+{
+    type Rejection = CsrfError;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let handle = parts
+            .extensions
+            .get::<SessionHandle>()
+            .cloned()
+            .ok_or(CsrfError::NoSession)?;
+        let mut session = handle.write().await;
+        if let Some(token) = session.get::<String>(SESSION_KEY) {
+            return Ok(Self(token));
+        }
+        let token = nanoid::nanoid!(32);
+        session.insert(SESSION_KEY, &token)?;
+        Ok(Self(token))
+    }
+}
+
+impl Render for CsrfToken {
+    fn render(&self) -> Markup {
+        html! {
+            input type="hidden" name=(FIELD_NAME) value=(self.0);
+        }
+    }
+}
+
+/// Drops the token, so that a new one is issued. Called when the user behind
+/// the session changes.
+pub fn reset(session: &mut axum_sessions::async_session::Session) {
+    session.remove(SESSION_KEY);
+}
+
+#[derive(Deserialize)]
+struct TokenField {
+    csrf_token: Option<String>,
+}
+
+/// Compares without exiting early, so that timing does not leak the token
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+/// Reads the url-encoded body of a state-changing request and checks its
+/// token against the session
+async fn verified_body<S, B>(req: Request<B>, state: &S) -> Result<Bytes, CsrfError>
+where
+    B: HttpBody + Send + 'static,
+    B::Data: Send,
+    B::Error: Into<BoxError>,
+    S: Send + Sync,
+{
+    let handle = req
+        .extensions()
+        .get::<SessionHandle>()
+        .cloned()
+        .ok_or(CsrfError::NoSession)?;
+    let body = Bytes::from_request(req, state)
+        .await
+        .map_err(|_| CsrfError::Body)?;
+    let field: TokenField = serde_urlencoded::from_bytes(&body)?;
+    let expected = handle.read().await.get::<String>(SESSION_KEY);
+    match (field.csrf_token, expected) {
+        (Some(token), Some(expected))
+            if constant_time_eq(token.as_bytes(), expected.as_bytes()) =>
+        {
+            Ok(body)
+        }
+        _ => Err(CsrfError::Invalid),
+    }
+}
+
+/// Like `axum::Form`, but rejects the request with a 403 unless it carries
+/// the session's token. Must not be combined with `WritableSession`, whose
+/// lock would never be released.
+pub struct CsrfForm<T>(pub T);
+
+#[async_trait]
+impl<S, B, T> FromRequest<S, B> for CsrfForm<T>
+where
+    B: HttpBody + Send + 'static,
+    B::Data: Send,
+    B::Error: Into<BoxError>,
+    S: Send + Sync,
+    T: DeserializeOwned,
+{
+    type Rejection = CsrfError;
+
+    async fn from_request(req: Request<B>, state: &S) -> Result<Self, Self::Rejection> {
+        let body = verified_body(req, state).await?;
+        Ok(Self(serde_urlencoded::from_bytes(&body)?))
+    }
+}
+
+/// Checks the token of a form without other fields, such as a lone button
+pub struct CsrfCheck;
+
+#[async_trait]
+impl<S, B> FromRequest<S, B> for CsrfCheck
+where
+    B: HttpBody + Send + 'static,
+    B::Data: Send,
+    B::Error: Into<BoxError>,
+    S: Send + Sync,
+{
+    type Rejection = CsrfError;
+
+    async fn from_request(req: Request<B>, state: &S) -> Result<Self, Self::Rejection> {
+        verified_body(req, state).await?;
+        Ok(Self)
+    }
+}
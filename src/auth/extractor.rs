@@ -1,5 +1,5 @@
 use crate::auth::user_role::{AuthorizationError, UserRole};
-use crate::configuration::SQLite3Settings;
+use crate::model::database::Database;
 use async_trait::async_trait;
 use axum::extract::FromRequestParts;
 use axum::http::request::Parts;
@@ -23,6 +23,7 @@ impl From<AuthorizationError> for UserAuthError {
     fn from(value: AuthorizationError) -> Self {
         match value {
             AuthorizationError::RusqliteError(e) => e.into(),
+            AuthorizationError::Unsupported(_) => Self::InternalError,
         }
     }
 }
@@ -33,13 +34,33 @@ pub struct UserAuth {
     pub role: UserRole,
 }
 
+impl UserAuth {
+    pub fn is_admin(&self) -> bool {
+        matches!(self.role, UserRole::Admin)
+    }
+    pub fn is_moderator(&self) -> bool {
+        matches!(self.role, UserRole::Moderator)
+    }
+    /// Whether the user may post topics, posts, and replies
+    pub fn can_post(&self) -> bool {
+        matches!(
+            self.role,
+            UserRole::Author | UserRole::Moderator | UserRole::Admin
+        )
+    }
+    /// Whether the user may edit content written by `author_user_id`
+    pub fn can_edit(&self, author_user_id: i64) -> bool {
+        self.can_post() && (self.id == author_user_id || self.is_moderator() || self.is_admin())
+    }
+}
+
 #[async_trait]
 impl<S> FromRequestParts<S> for UserAuth {
     type Rejection = UserAuthError;
 
-    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
         let db = parts
-            .extract::<Extension<SQLite3Settings>>()
+            .extract::<Extension<Database>>()
             .await
             .map_err(|_| UserAuthError::InternalError)?;
         let session = parts
@@ -52,10 +73,12 @@ impl<S> FromRequestParts<S> for UserAuth {
         let session = session.unwrap();
         let uid = session
             .get::<i64>("uid")
-            .ok_or(UserAuthError::InternalError)?;
+            .ok_or(UserAuthError::NotLoggedIn)?;
 
-        let conn = db.connect()?;
-        let role = UserRole::from_db(&conn, uid)?;
+        let role = db
+            .interact(move |conn| UserRole::from_db(conn, uid))
+            .await
+            .map_err(|_| UserAuthError::InternalError)??;
         Ok(Self { id: uid, role })
     }
 }
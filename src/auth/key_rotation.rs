@@ -0,0 +1,118 @@
+use std::sync::Arc;
+
+use axum::{
+    extract::State,
+    http::{header::COOKIE, HeaderValue, Request},
+};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use hmac::{Hmac, Mac};
+use secrecy::{ExposeSecret, Secret};
+use sha2::Sha256;
+
+use crate::configuration::SessionSecrets;
+
+/// Length of a base64 encoded HMAC-SHA256 digest
+const BASE64_DIGEST_LEN: usize = 44;
+/// `cookie::Key` uses the first 32 bytes of the master secret for signing
+const SIGNING_KEY_LEN: usize = 32;
+
+/// Re-signs session cookies signed by a retired secret with the current one
+#[derive(Clone)]
+pub struct KeyRotation {
+    cookie_name: String,
+    key: Arc<Secret<Vec<u8>>>,
+    old_keys: Arc<Vec<Secret<Vec<u8>>>>,
+}
+
+impl KeyRotation {
+    pub fn new(cookie_name: String, secrets: &SessionSecrets) -> Self {
+        let signing_key =
+            |k: &Secret<Vec<u8>>| Secret::new(k.expose_secret()[..SIGNING_KEY_LEN].to_vec());
+        Self {
+            cookie_name,
+            key: Arc::new(signing_key(&secrets.key)),
+            old_keys: Arc::new(secrets.old_keys.iter().map(signing_key).collect()),
+        }
+    }
+
+    fn mac(key: &Secret<Vec<u8>>, value: &str) -> Hmac<Sha256> {
+        let mut mac =
+            Hmac::<Sha256>::new_from_slice(key.expose_secret()).expect("HMAC takes any key size");
+        mac.update(value.as_bytes());
+        mac
+    }
+
+    fn verify(key: &Secret<Vec<u8>>, signed: &str) -> Option<String> {
+        if !signed.is_char_boundary(BASE64_DIGEST_LEN) {
+            return None;
+        }
+        let (digest, value) = signed.split_at(BASE64_DIGEST_LEN);
+        let digest = STANDARD.decode(digest).ok()?;
+        Self::mac(key, value)
+            .verify_slice(&digest)
+            .ok()
+            .map(|_| value.to_owned())
+    }
+
+    fn sign(&self, value: &str) -> String {
+        let digest = Self::mac(&self.key, value).finalize().into_bytes();
+        let mut signed = STANDARD.encode(digest);
+        signed.push_str(value);
+        signed
+    }
+
+    /// Returns the cookie re-signed with the current key, if it was signed by a retired key
+    fn rotate(&self, signed: &str) -> Option<String> {
+        if Self::verify(&self.key, signed).is_some() {
+            return None;
+        }
+        self.old_keys
+            .iter()
+            .find_map(|k| Self::verify(k, signed))
+            .map(|value| self.sign(&value))
+    }
+
+    fn rotate_header(&self, header: &str) -> Option<String> {
+        let mut rotated = false;
+        let cookies: Vec<String> = header
+            .split(';')
+            .map(|cookie| match cookie.trim().split_once('=') {
+                Some((name, value)) if name == self.cookie_name => {
+                    if let Some(value) = self.rotate(value) {
+                        rotated = true;
+                        format!("{}={}", name, value)
+                    } else {
+                        cookie.trim().to_owned()
+                    }
+                }
+                _ => cookie.trim().to_owned(),
+            })
+            .collect();
+        rotated.then(|| cookies.join("; "))
+    }
+}
+
+/// Middleware to run before the session layer, so that cookies signed with a
+/// retired key are still accepted
+pub async fn rotate_session_cookie<B>(
+    State(rotation): State<KeyRotation>,
+    mut request: Request<B>,
+) -> Request<B> {
+    if rotation.old_keys.is_empty() {
+        return request;
+    }
+    for (name, header) in request.headers_mut().iter_mut() {
+        if name != COOKIE {
+            continue;
+        }
+        let rotated = header
+            .to_str()
+            .ok()
+            .and_then(|h| rotation.rotate_header(h))
+            .and_then(|h| HeaderValue::from_str(&h).ok());
+        if let Some(rotated) = rotated {
+            *header = rotated;
+        }
+    }
+    request
+}
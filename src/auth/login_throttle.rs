@@ -0,0 +1,444 @@
+use chrono::{DateTime, Duration, Utc};
+use rusqlite::{params, Connection, OptionalExtension, TransactionBehavior};
+
+use crate::configuration::LoginSettings;
+
+/// Longest username stored with a failure, so that guesses cannot bloat the table
+const MAX_USERNAME_LENGTH: usize = 64;
+
+/// A failed login, as recorded for review
+#[derive(Debug)]
+pub struct LoginFailure {
+    pub id: i64,
+    pub username: String,
+    /// Set if `username` belonged to an account at the time
+    pub user_id: Option<i64>,
+    pub ip: String,
+    pub created_at: DateTime<Utc>,
+}
+
+/// An account locked after too many failures
+#[derive(Debug)]
+pub struct LockedAccount {
+    pub user_id: i64,
+    pub username: String,
+    pub locked_until: DateTime<Utc>,
+}
+
+/// Throttling state of an account
+struct Account {
+    id: i64,
+    last_login_at: Option<DateTime<Utc>>,
+    locked_until: Option<DateTime<Utc>>,
+}
+
+impl Account {
+    fn query(conn: &Connection, username: &str) -> Result<Option<Account>, rusqlite::Error> {
+        conn.query_row(
+            r#"
+            SELECT id, last_login_at, locked_until FROM users
+            WHERE username = ? AND phc IS NOT NULL
+            "#,
+            [username],
+            |row| {
+                Ok(Account {
+                    id: row.get(0)?,
+                    last_login_at: row.get(1)?,
+                    locked_until: row.get(2)?,
+                })
+            },
+        )
+        .optional()
+    }
+
+    /// Failures before the last login or the end of the last lockout are
+    /// forgiven
+    fn counts_since(&self, window_start: DateTime<Utc>) -> DateTime<Utc> {
+        [Some(window_start), self.last_login_at, self.locked_until]
+            .into_iter()
+            .flatten()
+            .max()
+            .unwrap_or(window_start)
+    }
+}
+
+/// Number of failures and the time of the latest one
+fn failures(
+    conn: &Connection,
+    column: &str,
+    value: &str,
+    since: DateTime<Utc>,
+) -> Result<(u32, Option<DateTime<Utc>>), rusqlite::Error> {
+    conn.query_row(
+        &format!(
+            r#"
+            SELECT count(*), max(created_at) FROM login_failures
+            WHERE {} = ? AND created_at > ?
+            "#,
+            column
+        ),
+        params![value, since],
+        |row| Ok((row.get(0)?, row.get(1)?)),
+    )
+}
+
+/// Wait after `failures` failures, none for the first `free` ones
+fn backoff(settings: &LoginSettings, failures: u32, free: u32) -> Duration {
+    if failures < free {
+        return Duration::zero();
+    }
+    let exponent = (failures - free).min(32);
+    let secs = settings
+        .backoff_base_secs
+        .saturating_mul(1 << exponent)
+        .min(settings.backoff_max_secs);
+    Duration::seconds(secs as i64)
+}
+
+fn truncate(username: &str) -> &str {
+    match username.char_indices().nth(MAX_USERNAME_LENGTH) {
+        Some((i, _)) => &username[..i],
+        None => username,
+    }
+}
+
+/// When the next attempt for `username` from `ip` will be allowed, or `None`
+/// if it is allowed now. An account whose failures reached `lockout_after`
+/// is locked here, so that the attempt reaching the limit may still succeed.
+fn retry_after(
+    conn: &Connection,
+    settings: &LoginSettings,
+    username: &str,
+    ip: &str,
+    now: DateTime<Utc>,
+) -> Result<Option<DateTime<Utc>>, rusqlite::Error> {
+    let window_start = now - Duration::seconds(settings.window_secs as i64);
+    let account = Account::query(conn, username)?;
+    if let Some(locked_until) = account.as_ref().and_then(|a| a.locked_until) {
+        if now < locked_until {
+            return Ok(Some(locked_until));
+        }
+    }
+    let since = account
+        .as_ref()
+        .map(|a| a.counts_since(window_start))
+        .unwrap_or(window_start);
+    let (username_failures, username_last) = failures(conn, "username", username, since)?;
+    if let Some(account) = &account {
+        if username_failures >= settings.lockout_after {
+            let locked_until = now + Duration::seconds(settings.lockout_secs as i64);
+            conn.execute(
+                r#"UPDATE users SET locked_until = ? WHERE id = ?"#,
+                params![locked_until, account.id],
+            )?;
+            tracing::warn!(
+                "user {} locked until {} after {} failed logins",
+                account.id,
+                locked_until,
+                username_failures
+            );
+            return Ok(Some(locked_until));
+        }
+    }
+    let (ip_failures, ip_last) = failures(conn, "ip", ip, window_start)?;
+    let retry = [
+        username_last
+            .map(|last| last + backoff(settings, username_failures, settings.backoff_after)),
+        ip_last.map(|last| last + backoff(settings, ip_failures, settings.ip_backoff_after)),
+    ]
+    .into_iter()
+    .flatten()
+    .max();
+    Ok(retry.filter(|&retry| now < retry))
+}
+
+/// An attempt counted as a failure until `record_success` forgives it
+#[derive(Debug)]
+pub struct Attempt {
+    failure_id: i64,
+}
+
+/// Checks that `username` may try a password from `ip` now and records the
+/// attempt as a failure, in one transaction that excludes other writers, so
+/// that concurrent attempts count against each other. Checked before
+/// verifying the password, so that throttled guesses cost no hashing.
+/// Returns when the next attempt will be allowed if throttled.
+pub fn begin_attempt(
+    conn: &mut Connection,
+    settings: &LoginSettings,
+    username: &str,
+    ip: &str,
+) -> Result<Result<Attempt, DateTime<Utc>>, rusqlite::Error> {
+    let now = Utc::now();
+    let username = truncate(username);
+    let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+    if let Some(retry_after) = retry_after(&tx, settings, username, ip, now)? {
+        // Keeps the lock, if one was just set
+        tx.commit()?;
+        return Ok(Err(retry_after));
+    }
+    let user_id = Account::query(&tx, username)?.map(|a| a.id);
+    let failure_id = tx.query_row(
+        r#"
+        INSERT INTO login_failures(username, user_id, ip, created_at)
+        VALUES (?, ?, ?, ?)
+        RETURNING id
+        "#,
+        params![username, user_id, ip, now],
+        |row| row.get(0),
+    )?;
+    tx.commit()?;
+    Ok(Ok(Attempt { failure_id }))
+}
+
+/// Forgives the attempt, whose password was right, and past failures of the
+/// user
+pub fn record_success(
+    conn: &Connection,
+    attempt: Attempt,
+    user_id: i64,
+) -> Result<(), rusqlite::Error> {
+    conn.execute(
+        r#"DELETE FROM login_failures WHERE id = ?"#,
+        [attempt.failure_id],
+    )?;
+    conn.execute(
+        r#"UPDATE users SET last_login_at = ? WHERE id = ?"#,
+        params![Utc::now(), user_id],
+    )?;
+    Ok(())
+}
+
+impl LoginFailure {
+    /// Most recent failures first
+    pub fn recent(conn: &Connection, limit: usize) -> Result<Vec<LoginFailure>, rusqlite::Error> {
+        let mut stmt = conn.prepare(
+            r#"
+            SELECT id, username, user_id, ip, created_at FROM login_failures
+            ORDER BY id DESC
+            LIMIT ?
+            "#,
+        )?;
+        let failures = stmt.query_map([limit as i64], |row| {
+            Ok(LoginFailure {
+                id: row.get(0)?,
+                username: row.get(1)?,
+                user_id: row.get(2)?,
+                ip: row.get(3)?,
+                created_at: row.get(4)?,
+            })
+        })?;
+        failures.collect()
+    }
+}
+
+impl LockedAccount {
+    /// Accounts currently locked, soonest to unlock first
+    pub fn list(conn: &Connection) -> Result<Vec<LockedAccount>, rusqlite::Error> {
+        let mut stmt = conn.prepare(
+            r#"
+            SELECT id, username, locked_until FROM users
+            WHERE locked_until > ?
+            ORDER BY locked_until
+            "#,
+        )?;
+        let accounts = stmt.query_map([Utc::now()], |row| {
+            Ok(LockedAccount {
+                user_id: row.get(0)?,
+                username: row.get(1)?,
+                locked_until: row.get(2)?,
+            })
+        })?;
+        accounts.collect()
+    }
+
+    /// Ends the lockout now, forgiving the failures that caused it. Returns
+    /// whether the account was locked.
+    pub fn unlock(conn: &Connection, user_id: i64) -> Result<bool, rusqlite::Error> {
+        let now = Utc::now();
+        Ok(conn.execute(
+            r#"UPDATE users SET locked_until = ? WHERE id = ? AND locked_until > ?"#,
+            params![now, user_id, now],
+        )? > 0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sql::migrations;
+
+    fn connect() -> Connection {
+        let mut conn = Connection::open_in_memory().unwrap();
+        migrations().to_latest(&mut conn).unwrap();
+        conn.execute(
+            r#"INSERT INTO users(id, username, phc) VALUES (2, 'alice', 'phc')"#,
+            [],
+        )
+        .unwrap();
+        conn
+    }
+
+    /// Backoff out of the way, so that only the limit under test applies
+    fn settings() -> LoginSettings {
+        LoginSettings {
+            backoff_after: 100,
+            ip_backoff_after: 100,
+            lockout_after: 100,
+            ..LoginSettings::default()
+        }
+    }
+
+    fn attempt(
+        conn: &mut Connection,
+        settings: &LoginSettings,
+        username: &str,
+        ip: &str,
+    ) -> Result<Attempt, DateTime<Utc>> {
+        begin_attempt(conn, settings, username, ip).unwrap()
+    }
+
+    #[test]
+    fn backoff_doubles_up_to_the_maximum() {
+        let settings = LoginSettings {
+            backoff_base_secs: 1,
+            backoff_max_secs: 10,
+            ..LoginSettings::default()
+        };
+        let waits = (0..9)
+            .map(|failures| backoff(&settings, failures, 3).num_seconds())
+            .collect::<Vec<_>>();
+        assert_eq!(waits, [0, 0, 0, 1, 2, 4, 8, 10, 10]);
+        assert_eq!(backoff(&settings, u32::MAX, 3).num_seconds(), 10);
+    }
+
+    #[test]
+    fn backoff_applies_past_the_free_failures() {
+        let mut conn = connect();
+        let settings = LoginSettings {
+            backoff_after: 2,
+            ..settings()
+        };
+        assert!(attempt(&mut conn, &settings, "alice", "10.0.0.1").is_ok());
+        assert!(attempt(&mut conn, &settings, "alice", "10.0.0.2").is_ok());
+        // A new address does not help once the username is throttled
+        let retry_after = attempt(&mut conn, &settings, "alice", "10.0.0.3").unwrap_err();
+        assert!(retry_after > Utc::now());
+    }
+
+    #[test]
+    fn locks_after_lockout_after_failures() {
+        let mut conn = connect();
+        let settings = LoginSettings {
+            lockout_after: 3,
+            ..settings()
+        };
+        for _ in 0..3 {
+            assert!(attempt(&mut conn, &settings, "alice", "10.0.0.1").is_ok());
+        }
+        let locked_until = attempt(&mut conn, &settings, "alice", "10.0.0.1").unwrap_err();
+        assert!(locked_until > Utc::now() + Duration::seconds(settings.lockout_secs as i64 - 60));
+        let locked = LockedAccount::list(&conn).unwrap();
+        assert_eq!(locked.len(), 1);
+        assert_eq!(locked[0].user_id, 2);
+        // Even from another address
+        assert_eq!(
+            attempt(&mut conn, &settings, "alice", "10.0.0.2").unwrap_err(),
+            locked_until
+        );
+    }
+
+    #[test]
+    fn unknown_usernames_are_never_locked() {
+        let mut conn = connect();
+        let settings = LoginSettings {
+            lockout_after: 1,
+            ..settings()
+        };
+        for _ in 0..3 {
+            assert!(attempt(&mut conn, &settings, "mallory", "10.0.0.1").is_ok());
+        }
+        assert!(LockedAccount::list(&conn).unwrap().is_empty());
+    }
+
+    #[test]
+    fn success_forgives_earlier_failures() {
+        let mut conn = connect();
+        let settings = LoginSettings {
+            lockout_after: 3,
+            ..settings()
+        };
+        for _ in 0..2 {
+            assert!(attempt(&mut conn, &settings, "alice", "10.0.0.1").is_ok());
+        }
+        let success = attempt(&mut conn, &settings, "alice", "10.0.0.1").unwrap();
+        record_success(&conn, success, 2).unwrap();
+        let failures: u32 = conn
+            .query_row(r#"SELECT count(*) FROM login_failures"#, [], |row| {
+                row.get(0)
+            })
+            .unwrap();
+        assert_eq!(failures, 2, "the successful attempt is not a failure");
+        for _ in 0..3 {
+            assert!(attempt(&mut conn, &settings, "alice", "10.0.0.1").is_ok());
+        }
+        assert!(attempt(&mut conn, &settings, "alice", "10.0.0.1").is_err());
+    }
+
+    #[test]
+    fn unlock_forgives_earlier_failures() {
+        let mut conn = connect();
+        let settings = LoginSettings {
+            lockout_after: 2,
+            ..settings()
+        };
+        for _ in 0..2 {
+            assert!(attempt(&mut conn, &settings, "alice", "10.0.0.1").is_ok());
+        }
+        assert!(attempt(&mut conn, &settings, "alice", "10.0.0.1").is_err());
+        assert!(LockedAccount::unlock(&conn, 2).unwrap());
+        assert!(!LockedAccount::unlock(&conn, 2).unwrap());
+        assert!(LockedAccount::list(&conn).unwrap().is_empty());
+        for _ in 0..2 {
+            assert!(attempt(&mut conn, &settings, "alice", "10.0.0.1").is_ok());
+        }
+        assert!(attempt(&mut conn, &settings, "alice", "10.0.0.1").is_err());
+    }
+
+    #[test]
+    fn ip_limit_applies_across_usernames() {
+        let mut conn = connect();
+        let settings = LoginSettings {
+            ip_backoff_after: 2,
+            ..settings()
+        };
+        assert!(attempt(&mut conn, &settings, "alice", "10.0.0.1").is_ok());
+        assert!(attempt(&mut conn, &settings, "bob", "10.0.0.1").is_ok());
+        assert!(attempt(&mut conn, &settings, "carol", "10.0.0.1").is_err());
+        // Neither the usernames nor other addresses are affected
+        assert!(attempt(&mut conn, &settings, "carol", "10.0.0.2").is_ok());
+        assert!(attempt(&mut conn, &settings, "alice", "10.0.0.2").is_ok());
+        assert!(LockedAccount::list(&conn).unwrap().is_empty());
+    }
+
+    #[test]
+    fn only_failures_within_the_window_count() {
+        let mut conn = connect();
+        let settings = LoginSettings {
+            lockout_after: 2,
+            ..settings()
+        };
+        let old = Utc::now() - Duration::seconds(settings.window_secs as i64 + 1);
+        for _ in 0..2 {
+            conn.execute(
+                r#"
+                INSERT INTO login_failures(username, user_id, ip, created_at)
+                VALUES ('alice', 2, '10.0.0.1', ?)
+                "#,
+                [old],
+            )
+            .unwrap();
+        }
+        assert!(attempt(&mut conn, &settings, "alice", "10.0.0.1").is_ok());
+    }
+}
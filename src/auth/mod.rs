@@ -1,3 +1,9 @@
 pub mod authentication;
+pub mod csrf;
 pub mod extractor;
+pub mod key_rotation;
+pub mod login_throttle;
+pub mod password;
+pub mod registration;
+pub mod session_store;
 pub mod user_role;
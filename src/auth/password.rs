@@ -0,0 +1,431 @@
+use chrono::{DateTime, Duration, Utc};
+use rusqlite::{params, Connection, OptionalExtension};
+use secrecy::{ExposeSecret, SecretString};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use thiserror::Error;
+use tracing::instrument;
+use validator::{Validate, ValidationError, ValidationErrors};
+
+use crate::auth::authentication::{self, compute_password_hash, verify_user_password};
+use crate::auth::login_throttle::{self, LockedAccount};
+use crate::auth::registration::validate_new_password;
+use crate::configuration::{LoginSettings, PasswordHashSettings};
+use crate::model::{
+    database::{Database, InteractError},
+    moderation_log::{LogRecord, ModerationAction},
+    user::User,
+};
+use crate::telemetry::spawn_blocking_with_tracing;
+
+#[derive(Deserialize, Debug, Validate)]
+#[validate(schema(function = "validate_change", skip_on_field_errors = false))]
+pub struct ChangePasswordForm {
+    pub current_password: SecretString,
+    pub new_password: SecretString,
+    pub new_password_confirmation: SecretString,
+}
+
+#[derive(Deserialize, Debug, Validate)]
+#[validate(schema(function = "validate_reset", skip_on_field_errors = false))]
+pub struct ResetPasswordForm {
+    pub password: SecretString,
+    pub password_confirmation: SecretString,
+}
+
+fn validate_change(form: &ChangePasswordForm) -> Result<(), ValidationError> {
+    validate_new_password(&form.new_password, &form.new_password_confirmation)
+}
+
+fn validate_reset(form: &ResetPasswordForm) -> Result<(), ValidationError> {
+    validate_new_password(&form.password, &form.password_confirmation)
+}
+
+#[derive(Debug, Error)]
+pub enum PasswordError {
+    #[error("Current password is incorrect")]
+    IncorrectPassword,
+    #[error("too many failed attempts, retry after {0}")]
+    Throttled(DateTime<Utc>),
+    #[error("Invalid or expired password reset link")]
+    InvalidToken,
+    #[error("no user named `{0}`")]
+    UnknownUsername(String),
+    #[error("forbidden: {0}")]
+    Forbidden(String),
+    #[error(transparent)]
+    Invalid(#[from] ValidationErrors),
+    #[error("Internal error")]
+    InternalError,
+    #[error(transparent)]
+    AuthenticationError(#[from] authentication::LoginError),
+    #[error(transparent)]
+    RusqliteError(#[from] rusqlite::Error),
+    #[error(transparent)]
+    InteractError(#[from] InteractError),
+}
+
+type Result<T, E = PasswordError> = std::result::Result<T, E>;
+
+async fn hash(settings: PasswordHashSettings, password: SecretString) -> Result<SecretString> {
+    spawn_blocking_with_tracing(move || compute_password_hash(&settings, password))
+        .await
+        .map_err(|_| PasswordError::InternalError)?
+        .map_err(|_| PasswordError::InternalError)
+}
+
+/// Replaces the password of a user. Every session of the user but
+/// `keep_session` is ended, unused reset links are revoked and any lockout is
+/// lifted. Returns the number of sessions ended.
+pub fn set_password(
+    conn: &Connection,
+    user_id: i64,
+    phc: &SecretString,
+    keep_session: Option<&str>,
+) -> Result<usize, rusqlite::Error> {
+    conn.execute(
+        r#"UPDATE users SET phc = ? WHERE id = ?"#,
+        params![phc.expose_secret(), user_id],
+    )?;
+    let sessions = conn.execute(
+        r#"DELETE FROM user_sessions WHERE session_user_id = ? AND id IS NOT ?"#,
+        params![user_id, keep_session],
+    )?;
+    conn.execute(
+        r#"DELETE FROM password_resets WHERE user_id = ? AND used_at IS NULL"#,
+        [user_id],
+    )?;
+    LockedAccount::unlock(conn, user_id)?;
+    Ok(sessions)
+}
+
+impl ChangePasswordForm {
+    /// Changes the password of the logged in user, keeping only the session
+    /// `session_id` logged in. Wrong current passwords count as failed logins
+    /// from `ip`. Returns the number of other sessions ended.
+    #[instrument(skip_all, fields(user_id=user_id))]
+    pub async fn change(
+        &self,
+        db: &Database,
+        settings: &LoginSettings,
+        hash_settings: PasswordHashSettings,
+        user_id: i64,
+        session_id: String,
+        ip: String,
+    ) -> Result<usize> {
+        self.validate()?;
+        let Some(user) = db
+            .interact(move |conn| User::query(conn, user_id))
+            .await??
+        else {
+            return Err(PasswordError::InternalError);
+        };
+        let settings = settings.clone();
+        let attempt = db
+            .interact(move |conn| {
+                login_throttle::begin_attempt(conn, &settings, &user.username, &ip)
+            })
+            .await??
+            .map_err(PasswordError::Throttled)?;
+        if !verify_user_password(db, user_id, self.current_password.clone()).await? {
+            return Err(PasswordError::IncorrectPassword);
+        }
+        db.interact(move |conn| login_throttle::record_success(conn, attempt, user_id))
+            .await??;
+        let phc = hash(hash_settings, self.new_password.clone()).await?;
+        let sessions = db
+            .transaction(move |tx| set_password(tx, user_id, &phc, Some(&session_id)))
+            .await??;
+        tracing::info!("user {} changed their password", user_id);
+        Ok(sessions)
+    }
+}
+
+/// A one-time link letting a user choose a new password without knowing the
+/// current one
+#[derive(Debug)]
+pub struct PasswordReset {
+    pub id: i64,
+    pub user_id: i64,
+    pub expires_at: DateTime<Utc>,
+}
+
+/// Only the digest of a token is stored. Tokens are random, so a fast hash is
+/// enough.
+fn token_hash(token: &str) -> String {
+    format!("{:x}", Sha256::digest(token.as_bytes()))
+}
+
+impl PasswordReset {
+    /// Issues a link for `username`, revoking the previous unused ones. Run it
+    /// in a transaction. Returns the link and its token, which is not stored
+    /// and cannot be shown again.
+    pub fn issue(
+        conn: &Connection,
+        admin_user_id: Option<i64>,
+        username: &str,
+        valid_for: Duration,
+    ) -> Result<(PasswordReset, String)> {
+        let user_id: i64 = conn
+            .query_row(
+                r#"SELECT id FROM users WHERE username = ? AND phc IS NOT NULL"#,
+                [username],
+                |row| row.get(0),
+            )
+            .optional()?
+            .ok_or_else(|| PasswordError::UnknownUsername(username.to_owned()))?;
+        conn.execute(
+            r#"DELETE FROM password_resets WHERE user_id = ? AND used_at IS NULL"#,
+            [user_id],
+        )?;
+        let token = nanoid::nanoid!(32);
+        let now = Utc::now();
+        let expires_at = now + valid_for;
+        let id = conn.query_row(
+            r#"
+            INSERT INTO password_resets(user_id, token_hash, created_by, created_at, expires_at)
+            VALUES (?, ?, ?, ?, ?)
+            RETURNING id
+            "#,
+            params![user_id, token_hash(&token), admin_user_id, now, expires_at],
+            |row| row.get(0),
+        )?;
+        LogRecord::new(admin_user_id, ModerationAction::IssuePasswordReset, user_id)
+            .insert(conn)?;
+        Ok((
+            PasswordReset {
+                id,
+                user_id,
+                expires_at,
+            },
+            token,
+        ))
+    }
+
+    /// The unused, unexpired link of `token`
+    pub fn query_by_token(
+        conn: &Connection,
+        token: &str,
+    ) -> Result<Option<PasswordReset>, rusqlite::Error> {
+        conn.query_row(
+            r#"
+            SELECT id, user_id, expires_at FROM password_resets
+            WHERE token_hash = ? AND used_at IS NULL AND expires_at > ?
+            "#,
+            params![token_hash(token), Utc::now()],
+            |row| {
+                Ok(PasswordReset {
+                    id: row.get(0)?,
+                    user_id: row.get(1)?,
+                    expires_at: row.get(2)?,
+                })
+            },
+        )
+        .optional()
+    }
+
+    /// Sets `phc` as the password of the user of `token` and uses the link
+    /// up, ending every session of the user. Run it in a transaction. Returns
+    /// the user's ID.
+    pub fn consume(conn: &Connection, token: &str, phc: &SecretString) -> Result<i64> {
+        let reset =
+            PasswordReset::query_by_token(conn, token)?.ok_or(PasswordError::InvalidToken)?;
+        conn.execute(
+            r#"UPDATE password_resets SET used_at = ? WHERE id = ?"#,
+            params![Utc::now(), reset.id],
+        )?;
+        set_password(conn, reset.user_id, phc, None)?;
+        Ok(reset.user_id)
+    }
+
+    /// Sets the password chosen through the link of `token`. Returns the
+    /// user's ID.
+    #[instrument(skip_all)]
+    pub async fn redeem(
+        db: &Database,
+        hash_settings: PasswordHashSettings,
+        token: String,
+        form: &ResetPasswordForm,
+    ) -> Result<i64> {
+        form.validate()?;
+        let phc = hash(hash_settings, form.password.clone()).await?;
+        let user_id = db
+            .transaction(move |tx| PasswordReset::consume(tx, &token, &phc))
+            .await??;
+        tracing::info!("user {} reset their password", user_id);
+        Ok(user_id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sql::migrations;
+
+    fn connect() -> Connection {
+        let mut conn = Connection::open_in_memory().unwrap();
+        migrations().to_latest(&mut conn).unwrap();
+        conn.execute(
+            r#"INSERT INTO users(id, username, phc) VALUES (2, 'alice', 'old'), (3, 'bob', 'old')"#,
+            [],
+        )
+        .unwrap();
+        conn
+    }
+
+    fn issue(conn: &Connection, valid_for: Duration) -> (PasswordReset, String) {
+        PasswordReset::issue(conn, Some(1), "alice", valid_for).unwrap()
+    }
+
+    fn phc(conn: &Connection, user_id: i64) -> String {
+        conn.query_row(r#"SELECT phc FROM users WHERE id = ?"#, [user_id], |row| {
+            row.get(0)
+        })
+        .unwrap()
+    }
+
+    fn sessions(conn: &Connection) -> Vec<String> {
+        let mut stmt = conn
+            .prepare(r#"SELECT id FROM user_sessions ORDER BY id"#)
+            .unwrap();
+        let ids = stmt.query_map([], |row| row.get(0)).unwrap();
+        ids.collect::<Result<_, _>>().unwrap()
+    }
+
+    fn add_session(conn: &Connection, id: &str, user_id: i64) {
+        conn.execute(
+            r#"INSERT INTO user_sessions(id, session_user_id, session) VALUES (?, ?, '{}')"#,
+            params![id, user_id],
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn only_the_token_digest_is_stored() {
+        let conn = connect();
+        let (reset, token) = issue(&conn, Duration::hours(1));
+        let stored: String = conn
+            .query_row(
+                r#"SELECT token_hash FROM password_resets WHERE id = ?"#,
+                [reset.id],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(stored, format!("{:x}", Sha256::digest(token.as_bytes())));
+        let leaked: i64 = conn
+            .query_row(
+                r#"SELECT count(*) FROM password_resets WHERE instr(token_hash, ?) > 0"#,
+                [&token],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(leaked, 0);
+    }
+
+    #[test]
+    fn token_works_only_once() {
+        let conn = connect();
+        let (reset, token) = issue(&conn, Duration::hours(1));
+        assert_eq!(reset.user_id, 2);
+        let user_id =
+            PasswordReset::consume(&conn, &token, &SecretString::new("new".into())).unwrap();
+        assert_eq!(user_id, 2);
+        assert_eq!(phc(&conn, 2), "new");
+        assert!(PasswordReset::query_by_token(&conn, &token)
+            .unwrap()
+            .is_none());
+        let again = PasswordReset::consume(&conn, &token, &SecretString::new("again".into()));
+        assert!(matches!(again, Err(PasswordError::InvalidToken)));
+        assert_eq!(phc(&conn, 2), "new");
+    }
+
+    #[test]
+    fn expired_token_is_rejected() {
+        let conn = connect();
+        let (_, token) = issue(&conn, Duration::seconds(-1));
+        assert!(PasswordReset::query_by_token(&conn, &token)
+            .unwrap()
+            .is_none());
+        let result = PasswordReset::consume(&conn, &token, &SecretString::new("new".into()));
+        assert!(matches!(result, Err(PasswordError::InvalidToken)));
+        assert_eq!(phc(&conn, 2), "old");
+    }
+
+    #[test]
+    fn unknown_token_is_rejected() {
+        let conn = connect();
+        issue(&conn, Duration::hours(1));
+        let result = PasswordReset::consume(&conn, "guess", &SecretString::new("new".into()));
+        assert!(matches!(result, Err(PasswordError::InvalidToken)));
+    }
+
+    #[test]
+    fn new_link_revokes_older_unused_ones() {
+        let conn = connect();
+        let (_, first) = issue(&conn, Duration::hours(1));
+        let (_, second) = issue(&conn, Duration::hours(1));
+        assert!(PasswordReset::query_by_token(&conn, &first)
+            .unwrap()
+            .is_none());
+        assert!(PasswordReset::query_by_token(&conn, &second)
+            .unwrap()
+            .is_some());
+        let links: i64 = conn
+            .query_row(r#"SELECT count(*) FROM password_resets"#, [], |row| {
+                row.get(0)
+            })
+            .unwrap();
+        assert_eq!(links, 1);
+    }
+
+    #[test]
+    fn issuing_is_logged_and_needs_an_active_account() {
+        let conn = connect();
+        issue(&conn, Duration::hours(1));
+        let action: String = conn
+            .query_row(
+                r#"SELECT action FROM moderation_log WHERE target_user_id = 2"#,
+                [],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(action, ModerationAction::IssuePasswordReset.as_str());
+        conn.execute(r#"UPDATE users SET phc = NULL WHERE id = 3"#, [])
+            .unwrap();
+        for username in ["bob", "nobody"] {
+            let result = PasswordReset::issue(&conn, None, username, Duration::hours(1));
+            assert!(matches!(result, Err(PasswordError::UnknownUsername(_))));
+        }
+    }
+
+    #[test]
+    fn set_password_keeps_only_the_given_session() {
+        let conn = connect();
+        for id in ["a", "b", "c"] {
+            add_session(&conn, id, 2);
+        }
+        add_session(&conn, "bob", 3);
+        let (_, token) = issue(&conn, Duration::hours(1));
+        let phc = SecretString::new("new".into());
+        assert_eq!(set_password(&conn, 2, &phc, Some("b")).unwrap(), 2);
+        assert_eq!(sessions(&conn), ["b", "bob"]);
+        // Pending links are revoked along with the old password
+        assert!(PasswordReset::query_by_token(&conn, &token)
+            .unwrap()
+            .is_none());
+        assert_eq!(set_password(&conn, 2, &phc, None).unwrap(), 1);
+        assert_eq!(sessions(&conn), ["bob"]);
+    }
+
+    #[test]
+    fn set_password_lifts_the_lockout() {
+        let conn = connect();
+        conn.execute(
+            r#"UPDATE users SET locked_until = ? WHERE id = 2"#,
+            [Utc::now() + Duration::hours(1)],
+        )
+        .unwrap();
+        set_password(&conn, 2, &SecretString::new("new".into()), None).unwrap();
+        assert!(LockedAccount::list(&conn).unwrap().is_empty());
+    }
+}
@@ -0,0 +1,156 @@
+use rusqlite::ErrorCode;
+use secrecy::{ExposeSecret, SecretString};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use subtle::{Choice, ConstantTimeEq};
+use thiserror::Error;
+use tracing::instrument;
+use validator::{Validate, ValidationError, ValidationErrors};
+
+use crate::auth::authentication::compute_password_hash;
+use crate::configuration::{PasswordHashSettings, RegistrationPolicy, RegistrationSettings};
+use crate::model::database::Database;
+use crate::telemetry::spawn_blocking_with_tracing;
+
+#[derive(Deserialize, Debug, Validate)]
+#[validate(schema(function = "validate_password", skip_on_field_errors = false))]
+pub struct RegistrationForm {
+    #[validate(length(min = 3, max = 32), custom = "validate_username")]
+    pub username: String,
+    pub password: SecretString,
+    pub password_confirmation: SecretString,
+    pub invite_code: Option<String>,
+}
+
+#[derive(Debug, Error)]
+pub enum RegistrationError {
+    #[error("Registration is closed")]
+    Closed,
+    #[error("Invalid invite code")]
+    InvalidInvite,
+    #[error("Username is taken")]
+    UsernameTaken,
+    #[error(transparent)]
+    Invalid(#[from] ValidationErrors),
+    #[error("Internal error")]
+    InternalError,
+    #[error(transparent)]
+    RusqliteError(#[from] rusqlite::Error),
+}
+
+type Result<T, E = RegistrationError> = std::result::Result<T, E>;
+
+/// Usernames are restricted to ASCII letters, digits, `-` and `_`
+pub fn validate_username(username: &str) -> Result<(), ValidationError> {
+    if username
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    {
+        Ok(())
+    } else {
+        Err(ValidationError::new("username_characters"))
+    }
+}
+
+pub fn validate_password_length(password: &SecretString) -> Result<(), ValidationError> {
+    let len = password.expose_secret().chars().count();
+    if (8..=128).contains(&len) {
+        Ok(())
+    } else {
+        Err(ValidationError::new("password_length"))
+    }
+}
+
+/// Checks a new password and its confirmation, as typed in any form
+pub fn validate_new_password(
+    password: &SecretString,
+    confirmation: &SecretString,
+) -> Result<(), ValidationError> {
+    validate_password_length(password)?;
+    if password.expose_secret() != confirmation.expose_secret() {
+        Err(ValidationError::new("password_confirmation"))
+    } else {
+        Ok(())
+    }
+}
+
+/// Passwords are checked at the form level, so that they never end up in validation errors
+fn validate_password(form: &RegistrationForm) -> Result<(), ValidationError> {
+    validate_new_password(&form.password, &form.password_confirmation)
+}
+
+/// Whether `code` is one of `invite_codes`. Every code is compared, as a
+/// SHA-256 digest and in constant time, so that the response time reveals
+/// neither a matching prefix nor the length of a code.
+fn is_invite_code(invite_codes: &[SecretString], code: &str) -> bool {
+    let digest = Sha256::digest(code.as_bytes());
+    let matched = invite_codes.iter().fold(Choice::from(0), |matched, c| {
+        matched | Sha256::digest(c.expose_secret().as_bytes()).ct_eq(&digest)
+    });
+    matched.into()
+}
+
+impl RegistrationForm {
+    /// Validates the form against the registration policy, then creates the user
+    #[instrument(skip_all, fields(username=self.username))]
+    pub async fn register(
+        &self,
+        db: &Database,
+        settings: &RegistrationSettings,
+        hash_settings: PasswordHashSettings,
+    ) -> Result<i64> {
+        match settings.policy {
+            RegistrationPolicy::Closed => return Err(RegistrationError::Closed),
+            RegistrationPolicy::Invite => {
+                let valid = self
+                    .invite_code
+                    .as_deref()
+                    .filter(|code| !code.is_empty())
+                    .map(|code| is_invite_code(&settings.invite_codes, code));
+                if valid != Some(true) {
+                    return Err(RegistrationError::InvalidInvite);
+                }
+            }
+            RegistrationPolicy::Open => {}
+        }
+        self.validate()?;
+        let password = self.password.clone();
+        let phc =
+            spawn_blocking_with_tracing(move || compute_password_hash(&hash_settings, password))
+                .await
+                .map_err(|_| RegistrationError::InternalError)?
+                .map_err(|_| RegistrationError::InternalError)?;
+        let username = self.username.clone();
+        db.interact(move |conn| {
+            conn.query_row(
+                r#"INSERT INTO users(username, phc) VALUES (?, ?) RETURNING id"#,
+                [&username, phc.expose_secret()],
+                |row| row.get(0),
+            )
+        })
+        .await
+        .map_err(|_| RegistrationError::InternalError)?
+        .map_err(|e| match e {
+            rusqlite::Error::SqliteFailure(f, _) if f.code == ErrorCode::ConstraintViolation => {
+                RegistrationError::UsernameTaken
+            }
+            e => e.into(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_configured_invite_codes_match() {
+        let codes = ["first-code", "second-code"].map(|c| SecretString::new(c.to_owned()));
+        assert!(is_invite_code(&codes, "first-code"));
+        assert!(is_invite_code(&codes, "second-code"));
+        for code in ["first", "first-code ", "second-codes", "FIRST-CODE", ""] {
+            assert!(!is_invite_code(&codes, code), "{:?}", code);
+        }
+        assert!(!is_invite_code(&[], "first-code"));
+    }
+}
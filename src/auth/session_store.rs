@@ -0,0 +1,118 @@
+use std::time::Duration;
+
+use async_trait::async_trait;
+use axum_sessions::async_session::{self, Session, SessionStore};
+use chrono::Utc;
+use rusqlite::{params, OptionalExtension};
+use tokio::task::JoinHandle;
+use tracing::instrument;
+
+use crate::model::database::Database;
+
+/// Session store backed by the `user_sessions` table
+#[derive(Debug, Clone)]
+pub struct SQLiteSessionStore {
+    db: Database,
+}
+
+impl SQLiteSessionStore {
+    pub fn new(db: Database) -> Self {
+        Self { db }
+    }
+
+    /// Deletes every session whose TTL has passed
+    #[instrument(skip_all)]
+    pub async fn cleanup(&self) -> async_session::Result<usize> {
+        let deleted = self
+            .db
+            .interact(|conn| {
+                conn.execute(
+                    r#"DELETE FROM user_sessions WHERE expires_at IS NOT NULL AND expires_at < ?"#,
+                    [Utc::now()],
+                )
+            })
+            .await??;
+        tracing::debug!("deleted {} expired sessions", deleted);
+        Ok(deleted)
+    }
+
+    /// Periodically deletes expired sessions in the background
+    pub fn spawn_cleanup_task(&self, period: Duration) -> JoinHandle<()> {
+        let store = self.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(period);
+            loop {
+                interval.tick().await;
+                if let Err(e) = store.cleanup().await {
+                    tracing::error!("Session cleanup failed: {}", e);
+                }
+            }
+        })
+    }
+}
+
+#[async_trait]
+impl SessionStore for SQLiteSessionStore {
+    async fn load_session(&self, cookie_value: String) -> async_session::Result<Option<Session>> {
+        let id = Session::id_from_cookie_value(&cookie_value)?;
+        let session: Option<String> = self
+            .db
+            .interact(move |conn| {
+                conn.query_row(
+                    r#"
+                    SELECT session FROM user_sessions
+                    WHERE id = ? AND (expires_at IS NULL OR expires_at > ?)
+                    "#,
+                    params![id, Utc::now()],
+                    |row| row.get(0),
+                )
+                .optional()
+            })
+            .await??;
+        if let Some(session) = session {
+            let session: Session = serde_json::from_str(&session)?;
+            Ok(session.validate())
+        } else {
+            Ok(None)
+        }
+    }
+
+    async fn store_session(&self, session: Session) -> async_session::Result<Option<String>> {
+        let id = session.id().to_owned();
+        let user_id = session.get::<i64>("uid");
+        let data = serde_json::to_string(&session)?;
+        let expiry = session.expiry().cloned();
+        self.db
+            .interact(move |conn| {
+                conn.execute(
+                    r#"
+                    INSERT INTO user_sessions(id, session_user_id, session, expires_at)
+                    VALUES (?, ?, ?, ?)
+                    ON CONFLICT(id) DO UPDATE SET
+                        session_user_id = excluded.session_user_id,
+                        session = excluded.session,
+                        expires_at = excluded.expires_at
+                    "#,
+                    params![id, user_id, data, expiry],
+                )
+            })
+            .await??;
+        session.reset_data_changed();
+        Ok(session.into_cookie_value())
+    }
+
+    async fn destroy_session(&self, session: Session) -> async_session::Result {
+        let id = session.id().to_owned();
+        self.db
+            .interact(move |conn| conn.execute(r#"DELETE FROM user_sessions WHERE id = ?"#, [id]))
+            .await??;
+        Ok(())
+    }
+
+    async fn clear_store(&self) -> async_session::Result {
+        self.db
+            .interact(|conn| conn.execute(r#"DELETE FROM user_sessions"#, []))
+            .await??;
+        Ok(())
+    }
+}
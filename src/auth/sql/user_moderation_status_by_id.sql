@@ -1,7 +1,8 @@
 -- (user_id)
 SELECT
-    banned_at
-    muted_until,
+    u.role,
+    u.banned_at,
+    u.muted_until,
     m.assigned_at moderator_assigned_at
 FROM
     users u
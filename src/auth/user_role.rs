@@ -1,18 +1,24 @@
-use crate::configuration::SQLite3Settings;
+use std::str::FromStr;
+
 use chrono::{DateTime, Utc};
 use rusqlite::{Connection, Row};
 use serde::{Deserialize, Serialize};
 use thiserror::Error;
 
+use crate::model::moderation_log::{LogRecord, ModerationAction};
+use crate::model::moderator::Moderator;
+
 #[derive(Error, Debug)]
 pub enum AuthorizationError {
+    #[error("unsupported role assignment: {0}")]
+    Unsupported(String),
     #[error(transparent)]
     RusqliteError(#[from] rusqlite::Error),
 }
 
 type Result<T, E = AuthorizationError> = std::result::Result<T, E>;
 
-#[derive(Debug, Serialize, Deserialize, Clone, Copy)]
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
 pub enum UserRole {
     /// A banned user may have partial viewing permission
     Banned,
@@ -26,25 +32,104 @@ pub enum UserRole {
     Admin,
 }
 
+impl FromStr for UserRole {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "banned" => Ok(Self::Banned),
+            "viewer" => Ok(Self::Viewer),
+            "author" => Ok(Self::Author),
+            "moderator" => Ok(Self::Moderator),
+            "admin" => Ok(Self::Admin),
+            _ => Err(format!("unknown role `{}`", s)),
+        }
+    }
+}
+
 impl UserRole {
-    pub fn from_db(conn: &Connection, user_id: i64) -> Result<Self> {
-        if user_id == 1 {
-            return Ok(Self::Admin);
+    /// Updates the stored role and moderation status of a user so that
+    /// `from_db` resolves to `role`. There is only one site administrator,
+    /// so making a user the admin demotes the current one to author.
+    pub fn assign(conn: &mut Connection, user_id: i64, role: UserRole) -> Result<()> {
+        let tx = conn.transaction()?;
+        let admins: i64 = tx.query_row(
+            r#"SELECT count(*) FROM users WHERE role = 'admin' AND id <> ?"#,
+            [user_id],
+            |row| row.get(0),
+        )?;
+        if admins == 0 && !matches!(role, Self::Admin) {
+            return Err(AuthorizationError::Unsupported(
+                "the last admin cannot be given another role".to_owned(),
+            ));
         }
+        if matches!(role, Self::Admin) {
+            let mut stmt =
+                tx.prepare(r#"SELECT id FROM users WHERE role = 'admin' AND id <> ?"#)?;
+            let previous = stmt
+                .query_map([user_id], |row| row.get::<_, i64>(0))?
+                .collect::<Result<Vec<_>, _>>()?;
+            drop(stmt);
+            for previous in previous {
+                tx.execute(
+                    r#"UPDATE users SET role = 'author' WHERE id = ?"#,
+                    [previous],
+                )?;
+                LogRecord::new(None, ModerationAction::SetRole, previous)
+                    .reason(&format!("Admin role handed over to UID {}", user_id))
+                    .insert(&tx)?;
+            }
+        }
+        let banned_at = matches!(role, Self::Banned).then(Utc::now);
+        // A ban keeps the base role, so that lifting it restores the user
+        // as they were
+        let base_role = match role {
+            Self::Banned => None,
+            Self::Viewer => Some("viewer"),
+            Self::Author | Self::Moderator => Some("author"),
+            Self::Admin => Some("admin"),
+        };
+        tx.execute(
+            r#"
+            UPDATE users SET
+                role = coalesce(?, CASE role WHEN 'admin' THEN 'author' ELSE role END),
+                banned_at = ?, muted_until = NULL, ban_reason = NULL, mute_reason = NULL
+            WHERE id = ?
+            "#,
+            rusqlite::params![base_role, banned_at, user_id],
+        )?;
+        LogRecord::new(None, ModerationAction::SetRole, user_id)
+            .reason(&format!("Role set to {:?}", role))
+            .insert(&tx)?;
+        if matches!(role, Self::Moderator) {
+            Moderator::insert(&tx, user_id)?;
+        } else {
+            Moderator::remove(&tx, user_id, &format!("Role set to {:?}", role))?;
+        }
+        tx.commit()?;
+        Ok(())
+    }
+    pub fn from_db(conn: &Connection, user_id: i64) -> Result<Self> {
         Ok(conn.query_row(
             include_str!("sql/user_moderation_status_by_id.sql"),
             [user_id],
             Self::from_row,
         )?)
     }
+    /// Admins cannot be sanctioned. For everyone else a ban takes
+    /// precedence over a mute or the viewer role, which in turn take
+    /// precedence over moderation.
     fn from_row(row: &Row<'_>) -> Result<Self, rusqlite::Error> {
+        let role: String = row.get("role")?;
         let banned_at: Option<DateTime<Utc>> = row.get("banned_at")?;
         let muted_until: Option<DateTime<Utc>> = row.get("muted_until")?;
         let moderator_assigned_at: Option<DateTime<Utc>> = row.get("moderator_assigned_at")?;
         let now = Utc::now();
-        if banned_at.map(|b| b < now) == Some(true) {
+        if role == "admin" {
+            Ok(Self::Admin)
+        } else if banned_at.map(|b| b < now) == Some(true) {
             Ok(Self::Banned)
-        } else if muted_until.map(|m| now < m) == Some(true) {
+        } else if role == "viewer" || muted_until.map(|m| now < m) == Some(true) {
             Ok(Self::Viewer)
         } else if moderator_assigned_at.map(|m| m < now) == Some(true) {
             Ok(Self::Moderator)
@@ -53,3 +138,215 @@ impl UserRole {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use chrono::Duration;
+    use rusqlite::params;
+
+    use super::*;
+    use crate::sql::migrations;
+
+    /// Resolves a role from literal column values, as `from_db` would
+    fn resolve(
+        role: &str,
+        banned_at: Option<DateTime<Utc>>,
+        muted_until: Option<DateTime<Utc>>,
+        moderator_assigned_at: Option<DateTime<Utc>>,
+    ) -> UserRole {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.query_row(
+            r#"SELECT ? role, ? banned_at, ? muted_until, ? moderator_assigned_at"#,
+            params![role, banned_at, muted_until, moderator_assigned_at],
+            UserRole::from_row,
+        )
+        .unwrap()
+    }
+
+    fn timestamps() -> [Option<DateTime<Utc>>; 3] {
+        let now = Utc::now();
+        [
+            None,
+            Some(now - Duration::hours(1)),
+            Some(now + Duration::hours(1)),
+        ]
+    }
+
+    fn is_past(t: Option<DateTime<Utc>>) -> bool {
+        t.map(|t| t < Utc::now()) == Some(true)
+    }
+
+    #[test]
+    fn admin_ignores_every_timestamp() {
+        for banned_at in timestamps() {
+            for muted_until in timestamps() {
+                for assigned_at in timestamps() {
+                    assert_eq!(
+                        resolve("admin", banned_at, muted_until, assigned_at),
+                        UserRole::Admin,
+                        "banned_at={:?} muted_until={:?} assigned_at={:?}",
+                        banned_at,
+                        muted_until,
+                        assigned_at
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn past_ban_wins_over_mute_viewer_and_moderator() {
+        let banned_at = timestamps()[1];
+        for role in ["author", "viewer"] {
+            for muted_until in timestamps() {
+                for assigned_at in timestamps() {
+                    assert_eq!(
+                        resolve(role, banned_at, muted_until, assigned_at),
+                        UserRole::Banned,
+                        "role={} muted_until={:?} assigned_at={:?}",
+                        role,
+                        muted_until,
+                        assigned_at
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn every_combination() {
+        for role in ["author", "viewer"] {
+            for banned_at in timestamps() {
+                for muted_until in timestamps() {
+                    for assigned_at in timestamps() {
+                        let expected = if is_past(banned_at) {
+                            UserRole::Banned
+                        } else if role == "viewer" || muted_until > Some(Utc::now()) {
+                            UserRole::Viewer
+                        } else if is_past(assigned_at) {
+                            UserRole::Moderator
+                        } else {
+                            UserRole::Author
+                        };
+                        assert_eq!(
+                            resolve(role, banned_at, muted_until, assigned_at),
+                            expected,
+                            "role={} banned_at={:?} muted_until={:?} assigned_at={:?}",
+                            role,
+                            banned_at,
+                            muted_until,
+                            assigned_at
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn future_ban_is_not_in_effect() {
+        let [_, _, future] = timestamps();
+        assert_eq!(resolve("author", future, None, None), UserRole::Author);
+    }
+
+    #[test]
+    fn expired_mute_is_lifted() {
+        let [_, past, _] = timestamps();
+        assert_eq!(resolve("author", None, past, None), UserRole::Author);
+        assert_eq!(resolve("author", None, past, past), UserRole::Moderator);
+    }
+
+    #[test]
+    fn active_mute_demotes_moderator_to_viewer() {
+        let [_, past, future] = timestamps();
+        assert_eq!(resolve("author", None, future, past), UserRole::Viewer);
+    }
+
+    #[test]
+    fn viewer_role_is_read_only_without_mute() {
+        assert_eq!(resolve("viewer", None, None, None), UserRole::Viewer);
+    }
+
+    #[test]
+    fn from_db_reads_every_column() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        migrations().to_latest(&mut conn).unwrap();
+        conn.execute(
+            r#"INSERT INTO users(id, username, muted_until) VALUES (2, 'muted', ?)"#,
+            [Utc::now() + Duration::hours(1)],
+        )
+        .unwrap();
+        assert_eq!(UserRole::from_db(&conn, 2).unwrap(), UserRole::Viewer);
+    }
+
+    #[test]
+    fn assign_round_trips_through_from_db() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        migrations().to_latest(&mut conn).unwrap();
+        conn.execute(r#"INSERT INTO users(id, username) VALUES (2, 'user')"#, [])
+            .unwrap();
+        for role in [
+            UserRole::Viewer,
+            UserRole::Moderator,
+            UserRole::Banned,
+            UserRole::Author,
+            UserRole::Admin,
+        ] {
+            UserRole::assign(&mut conn, 2, role).unwrap();
+            assert_eq!(UserRole::from_db(&conn, 2).unwrap(), role);
+        }
+    }
+
+    #[test]
+    fn new_admin_replaces_the_current_one() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        // The migrations seed user 1 as the admin
+        migrations().to_latest(&mut conn).unwrap();
+        conn.execute(r#"INSERT INTO users(id, username) VALUES (2, 'user')"#, [])
+            .unwrap();
+        UserRole::assign(&mut conn, 2, UserRole::Admin).unwrap();
+        assert_eq!(UserRole::from_db(&conn, 2).unwrap(), UserRole::Admin);
+        assert_eq!(UserRole::from_db(&conn, 1).unwrap(), UserRole::Author);
+        let admins: i64 = conn
+            .query_row(
+                r#"SELECT count(*) FROM users WHERE role = 'admin'"#,
+                [],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(admins, 1);
+        let logged: i64 = conn
+            .query_row(
+                r#"SELECT count(*) FROM moderation_log WHERE action = 'set_role' AND target_user_id = 1"#,
+                [],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(logged, 1);
+
+        // Handing the role back restores the previous admin alone
+        UserRole::assign(&mut conn, 1, UserRole::Admin).unwrap();
+        assert_eq!(UserRole::from_db(&conn, 1).unwrap(), UserRole::Admin);
+        assert_eq!(UserRole::from_db(&conn, 2).unwrap(), UserRole::Author);
+    }
+
+    #[test]
+    fn reassigning_the_admin_keeps_them() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        migrations().to_latest(&mut conn).unwrap();
+        UserRole::assign(&mut conn, 1, UserRole::Admin).unwrap();
+        assert_eq!(UserRole::from_db(&conn, 1).unwrap(), UserRole::Admin);
+    }
+
+    #[test]
+    fn last_admin_keeps_role() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        // The migrations seed user 1 as the admin
+        migrations().to_latest(&mut conn).unwrap();
+        assert!(matches!(
+            UserRole::assign(&mut conn, 1, UserRole::Author),
+            Err(AuthorizationError::Unsupported(_))
+        ));
+        assert_eq!(UserRole::from_db(&conn, 1).unwrap(), UserRole::Admin);
+    }
+}
@@ -0,0 +1,296 @@
+use std::path::PathBuf;
+
+use clap::{Args, Parser, Subcommand};
+use color_eyre::eyre::eyre;
+use rusqlite::params;
+use secrecy::{ExposeSecret, SecretString};
+
+use crate::auth::authentication::compute_password_hash;
+use crate::auth::password::{set_password, PasswordReset};
+use crate::auth::registration::{validate_password_length, validate_username};
+use crate::auth::user_role::UserRole;
+use crate::configuration::{get_configuration, Environment, Settings};
+use crate::model::moderation_log::{LogRecord, ModerationAction};
+use crate::model::moderator::Moderator;
+use crate::model::user::User;
+use crate::model::wipe::wipe;
+use crate::sql::{current_version, migrations, MIGRATION_FILES};
+use crate::startup;
+use crate::telemetry::init_telemetry;
+
+/// Every setting may also be overridden with `REFORUM__<SECTION>__<KEY>`
+/// environment variables, e.g. `REFORUM__DATABASE__CONNECTION`.
+#[derive(Parser)]
+#[command(version, about)]
+pub struct Cli {
+    /// Path to the base configuration file
+    #[arg(
+        long,
+        global = true,
+        env = "REFORUM_CONFIG",
+        default_value = "configuration/base.toml"
+    )]
+    config: PathBuf,
+    /// Profile whose `<environment>.toml` overrides the base configuration
+    #[arg(
+        long,
+        global = true,
+        env = "REFORUM_ENVIRONMENT",
+        default_value = "local"
+    )]
+    environment: Environment,
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Run the forum server (default)
+    Serve,
+    /// Manage the database schema
+    Migrate {
+        #[command(subcommand)]
+        action: MigrateAction,
+    },
+    /// Make a user the administrator and set their password, creating the
+    /// account if needed. The current administrator becomes an author.
+    CreateAdmin {
+        username: String,
+        #[command(flatten)]
+        password: PasswordInput,
+    },
+    /// Set a new password for a user, unlock their account and log out all
+    /// of their sessions
+    ResetPassword {
+        username: String,
+        #[command(flatten)]
+        password: PasswordInput,
+    },
+    /// Issue a one-time link letting a user choose a new password
+    PasswordResetLink { username: String },
+    /// Change the role of a user
+    SetRole { username: String, role: UserRole },
+    /// Make a user a moderator
+    Promote { username: String },
+    /// Revoke moderation from a user
+    Demote {
+        username: String,
+        /// Recorded in the past moderators history
+        #[arg(long)]
+        reason: String,
+    },
+    /// Delete every topic, post and reply of a user and anonymize the account
+    WipeUser { username: String },
+    /// Load the configuration and check that the database is usable
+    CheckConfig,
+}
+
+#[derive(Subcommand)]
+enum MigrateAction {
+    /// Apply all pending migrations
+    Up,
+    /// Revert migrations until the schema is at `version`
+    Down { version: usize },
+    /// List applied and pending migrations
+    Status,
+}
+
+#[derive(Args)]
+struct PasswordInput {
+    /// Read the password from the first line of stdin instead of prompting
+    #[arg(long)]
+    password_stdin: bool,
+}
+
+impl PasswordInput {
+    fn read(&self) -> color_eyre::Result<SecretString> {
+        let password = if self.password_stdin {
+            let mut line = String::new();
+            std::io::stdin().read_line(&mut line)?;
+            SecretString::new(line.trim_end_matches(['\r', '\n']).to_owned())
+        } else {
+            let password = SecretString::new(rpassword::prompt_password("Password: ")?);
+            let confirmation = rpassword::prompt_password("Confirm password: ")?;
+            if password.expose_secret() != &confirmation {
+                return Err(eyre!("passwords do not match"));
+            }
+            password
+        };
+        validate_password_length(&password)
+            .map_err(|_| eyre!("password must be between 8 and 128 characters"))?;
+        Ok(password)
+    }
+}
+
+pub async fn run() -> color_eyre::Result<()> {
+    let cli = Cli::parse();
+    init_telemetry();
+    let configuration = get_configuration(&cli.config, cli.environment)?;
+    match cli.command.unwrap_or(Command::Serve) {
+        Command::Serve => startup::run(configuration).await,
+        Command::Migrate { action } => migrate(&configuration, action),
+        Command::CreateAdmin { username, password } => {
+            create_admin(&configuration, &username, password.read()?)
+        }
+        Command::ResetPassword { username, password } => {
+            reset_password(&configuration, &username, password.read()?)
+        }
+        Command::PasswordResetLink { username } => password_reset_link(&configuration, &username),
+        Command::SetRole { username, role } => set_role(&configuration, &username, role),
+        Command::Promote { username } => promote(&configuration, &username),
+        Command::Demote { username, reason } => demote(&configuration, &username, &reason),
+        Command::WipeUser { username } => wipe_user(&configuration, &username),
+        Command::CheckConfig => check_config(&configuration),
+    }
+}
+
+fn migrate(configuration: &Settings, action: MigrateAction) -> color_eyre::Result<()> {
+    let mut conn = configuration.database.connect()?;
+    match action {
+        MigrateAction::Up => migrations().to_latest(&mut conn)?,
+        MigrateAction::Down { version } => {
+            if version > current_version(&conn)? {
+                return Err(eyre!("schema is already below version {}", version));
+            }
+            migrations().to_version(&mut conn, version)?
+        }
+        MigrateAction::Status => {
+            let version = current_version(&conn)?;
+            println!("Schema version: {}", version);
+            for file in MIGRATION_FILES {
+                let applied = if file.number < version { "x" } else { " " };
+                println!("[{}] {:02}-{}", applied, file.number, file.name);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Opens a connection to a fully migrated database
+fn connect(configuration: &Settings) -> color_eyre::Result<rusqlite::Connection> {
+    let mut conn = configuration.database.connect()?;
+    migrations().to_latest(&mut conn)?;
+    Ok(conn)
+}
+
+fn user_id(conn: &rusqlite::Connection, username: &str) -> color_eyre::Result<i64> {
+    User::id_by_username(conn, username)?.ok_or_else(|| eyre!("no user named `{}`", username))
+}
+
+fn create_admin(
+    configuration: &Settings,
+    username: &str,
+    password: SecretString,
+) -> color_eyre::Result<()> {
+    validate_username(username).map_err(|_| eyre!("invalid username `{}`", username))?;
+    let mut conn = connect(configuration)?;
+    let phc = compute_password_hash(&configuration.password_hash, password)
+        .map_err(|e| eyre!("{}", e))?;
+    let user_id: i64 = conn.query_row(
+        r#"
+        INSERT INTO users(username, phc) VALUES (?, ?)
+        ON CONFLICT(username) DO UPDATE SET phc = excluded.phc
+        RETURNING id
+        "#,
+        params![username, phc.expose_secret()],
+        |row| row.get(0),
+    )?;
+    UserRole::assign(&mut conn, user_id, UserRole::Admin)?;
+    conn.execute(
+        r#"DELETE FROM user_sessions WHERE session_user_id = ?"#,
+        [user_id],
+    )?;
+    println!("`{}` (UID {}) is now the administrator", username, user_id);
+    Ok(())
+}
+
+fn reset_password(
+    configuration: &Settings,
+    username: &str,
+    password: SecretString,
+) -> color_eyre::Result<()> {
+    let mut conn = connect(configuration)?;
+    let user_id = user_id(&conn, username)?;
+    let phc = compute_password_hash(&configuration.password_hash, password)
+        .map_err(|e| eyre!("{}", e))?;
+    let tx = conn.transaction()?;
+    let sessions = set_password(&tx, user_id, &phc, None)?;
+    tx.commit()?;
+    println!(
+        "Password of `{}` reset, {} session(s) logged out",
+        username, sessions
+    );
+    Ok(())
+}
+
+fn password_reset_link(configuration: &Settings, username: &str) -> color_eyre::Result<()> {
+    let mut conn = connect(configuration)?;
+    let valid_for = chrono::Duration::seconds(configuration.accounts.password_reset_secs as i64);
+    let tx = conn.transaction()?;
+    let (reset, token) = PasswordReset::issue(&tx, None, username, valid_for)?;
+    tx.commit()?;
+    println!(
+        "/password-reset/{}\nvalid for `{}` (UID {}) until {}",
+        token, username, reset.user_id, reset.expires_at
+    );
+    Ok(())
+}
+
+fn set_role(configuration: &Settings, username: &str, role: UserRole) -> color_eyre::Result<()> {
+    let mut conn = connect(configuration)?;
+    let user_id = user_id(&conn, username)?;
+    UserRole::assign(&mut conn, user_id, role)?;
+    println!("`{}` is now {:?}", username, role);
+    Ok(())
+}
+
+fn promote(configuration: &Settings, username: &str) -> color_eyre::Result<()> {
+    let mut conn = connect(configuration)?;
+    let user_id = user_id(&conn, username)?;
+    Moderator::promote(&mut conn, None, user_id)?;
+    println!("`{}` is now a moderator", username);
+    Ok(())
+}
+
+fn demote(configuration: &Settings, username: &str, reason: &str) -> color_eyre::Result<()> {
+    let mut conn = connect(configuration)?;
+    let user_id = user_id(&conn, username)?;
+    Moderator::demote(&mut conn, None, user_id, reason)?;
+    println!("`{}` is no longer a moderator", username);
+    Ok(())
+}
+
+fn wipe_user(configuration: &Settings, username: &str) -> color_eyre::Result<()> {
+    let mut conn = connect(configuration)?;
+    let user_id = user_id(&conn, username)?;
+    if UserRole::from_db(&conn, user_id)? == UserRole::Admin {
+        return Err(eyre!("an administrator cannot be wiped"));
+    }
+    let tx = conn.transaction()?;
+    LogRecord::new(None, ModerationAction::WipeAccount, user_id).insert(&tx)?;
+    wipe(&tx, user_id)?;
+    tx.commit()?;
+    println!("`{}` (UID {}) has been wiped", username, user_id);
+    Ok(())
+}
+
+fn check_config(configuration: &Settings) -> color_eyre::Result<()> {
+    configuration.session_secret.secrets()?;
+    let conn = configuration.database.connect()?;
+    configuration.database.pragmas.verify(&conn)?;
+    println!("Listening on {}", configuration.address());
+    println!(
+        "Database `{}` at schema version {} of {}",
+        configuration.database.connection,
+        current_version(&conn)?,
+        MIGRATION_FILES.len()
+    );
+    println!("Registration: {:?}", configuration.registration.policy);
+    let hash = &configuration.password_hash;
+    println!(
+        "Password hashes: {:?}, m={} t={} p={}",
+        hash.algorithm, hash.memory_kib, hash.iterations, hash.parallelism
+    );
+    println!("Configuration OK");
+    Ok(())
+}
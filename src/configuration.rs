@@ -1,33 +1,546 @@
+use std::{
+    net::{IpAddr, SocketAddr},
+    path::Path,
+};
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use rand::Rng;
+use secrecy::{ExposeSecret, Secret, SecretString};
 use serde::Deserialize;
+use thiserror::Error;
+use validator::{Validate, ValidationError, ValidationErrors};
 
-#[derive(Deserialize)]
+#[derive(Deserialize, Validate)]
 pub struct Settings {
     pub session_cookie_name: SessionCookieName,
+    #[serde(default)]
+    pub session_secret: SessionSecretSettings,
+    #[validate]
     pub database: SQLite3Settings,
-    pub listen: String,
+    #[serde(default)]
+    pub registration: RegistrationSettings,
+    #[serde(default)]
+    pub accounts: AccountSettings,
+    #[serde(default)]
+    #[validate]
+    pub login: LoginSettings,
+    #[serde(default)]
+    pub posting: PostingSettings,
+    #[serde(default)]
+    #[validate]
+    pub password_hash: PasswordHashSettings,
+    pub listen: IpAddr,
+    #[validate(range(min = 1))]
     pub port: u16,
 }
 
-#[derive(Deserialize, Clone)]
+impl Settings {
+    pub fn address(&self) -> SocketAddr {
+        SocketAddr::new(self.listen, self.port)
+    }
+}
+
+#[derive(Deserialize, Clone, Debug, Validate)]
 pub struct SQLite3Settings {
+    #[validate(length(min = 1))]
     pub connection: String,
+    /// Number of pooled connections
+    #[serde(default = "default_pool_size")]
+    #[validate(range(min = 1, max = 64))]
+    pub pool_size: usize,
+    #[serde(default)]
+    pub pragmas: PragmaSettings,
+}
+
+fn default_pool_size() -> usize {
+    4
 }
 
 impl SQLite3Settings {
+    /// Opens a connection with the configured pragmas applied
     pub fn connect(&self) -> Result<rusqlite::Connection, rusqlite::Error> {
-        rusqlite::Connection::open(&self.connection)
+        let conn = rusqlite::Connection::open(&self.connection)?;
+        self.pragmas.apply(&conn)?;
+        Ok(conn)
+    }
+}
+
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum JournalMode {
+    Delete,
+    Truncate,
+    Persist,
+    Memory,
+    Wal,
+    Off,
+}
+
+impl JournalMode {
+    fn as_str(&self) -> &'static str {
+        match self {
+            JournalMode::Delete => "delete",
+            JournalMode::Truncate => "truncate",
+            JournalMode::Persist => "persist",
+            JournalMode::Memory => "memory",
+            JournalMode::Wal => "wal",
+            JournalMode::Off => "off",
+        }
+    }
+}
+
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Synchronous {
+    Off = 0,
+    Normal = 1,
+    Full = 2,
+    Extra = 3,
+}
+
+#[derive(Error, Debug)]
+pub enum PragmaError {
+    #[error("pragma `{pragma}` is `{actual}`, expected `{expected}`")]
+    Mismatch {
+        pragma: &'static str,
+        expected: String,
+        actual: String,
+    },
+    #[error(transparent)]
+    RusqliteError(#[from] rusqlite::Error),
+}
+
+/// Pragmas applied to every connection. Unset pragmas keep the SQLite defaults.
+#[derive(Deserialize, Clone, Debug)]
+pub struct PragmaSettings {
+    pub journal_mode: Option<JournalMode>,
+    pub synchronous: Option<Synchronous>,
+    /// Required for the `ON DELETE CASCADE` clauses in the schema
+    #[serde(default = "default_foreign_keys")]
+    pub foreign_keys: bool,
+    /// Milliseconds to wait on a locked database
+    #[serde(default = "default_busy_timeout")]
+    pub busy_timeout: u64,
+    /// Pages if positive, KiB if negative
+    pub cache_size: Option<i64>,
+}
+
+fn default_foreign_keys() -> bool {
+    true
+}
+
+fn default_busy_timeout() -> u64 {
+    5000
+}
+
+impl Default for PragmaSettings {
+    fn default() -> Self {
+        Self {
+            journal_mode: None,
+            synchronous: None,
+            foreign_keys: default_foreign_keys(),
+            busy_timeout: default_busy_timeout(),
+            cache_size: None,
+        }
+    }
+}
+
+impl PragmaSettings {
+    fn apply(&self, conn: &rusqlite::Connection) -> Result<(), rusqlite::Error> {
+        if let Some(journal_mode) = self.journal_mode {
+            // `journal_mode` returns the new mode, which `verify` checks
+            conn.pragma_update_and_check(None, "journal_mode", journal_mode.as_str(), |_| Ok(()))?;
+        }
+        if let Some(synchronous) = self.synchronous {
+            conn.pragma_update(None, "synchronous", synchronous as i64)?;
+        }
+        conn.pragma_update(None, "foreign_keys", self.foreign_keys)?;
+        conn.busy_timeout(std::time::Duration::from_millis(self.busy_timeout))?;
+        if let Some(cache_size) = self.cache_size {
+            conn.pragma_update(None, "cache_size", cache_size)?;
+        }
+        Ok(())
+    }
+
+    /// Checks that the pragmas took effect on `conn`
+    pub fn verify(&self, conn: &rusqlite::Connection) -> Result<(), PragmaError> {
+        fn check<T: ToString + PartialEq + rusqlite::types::FromSql>(
+            conn: &rusqlite::Connection,
+            pragma: &'static str,
+            expected: T,
+        ) -> Result<(), PragmaError> {
+            let actual: T = conn.pragma_query_value(None, pragma, |row| row.get(0))?;
+            if actual == expected {
+                Ok(())
+            } else {
+                Err(PragmaError::Mismatch {
+                    pragma,
+                    expected: expected.to_string(),
+                    actual: actual.to_string(),
+                })
+            }
+        }
+        if let Some(journal_mode) = self.journal_mode {
+            check(conn, "journal_mode", journal_mode.as_str().to_owned())?;
+        }
+        if let Some(synchronous) = self.synchronous {
+            check(conn, "synchronous", synchronous as i64)?;
+        }
+        check(conn, "foreign_keys", self.foreign_keys)?;
+        check(conn, "busy_timeout", self.busy_timeout as i64)?;
+        if let Some(cache_size) = self.cache_size {
+            check(conn, "cache_size", cache_size)?;
+        }
+        Ok(())
     }
 }
 
 #[derive(Deserialize, Clone)]
 pub struct SessionCookieName(pub String);
 
-pub fn get_configuration() -> Result<Settings, config::ConfigError> {
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum RegistrationPolicy {
+    /// Anyone may register
+    #[default]
+    Open,
+    /// Registration requires one of the configured invite codes
+    Invite,
+    /// Nobody may register
+    Closed,
+}
+
+#[derive(Deserialize, Clone, Default)]
+pub struct RegistrationSettings {
+    #[serde(default)]
+    pub policy: RegistrationPolicy,
+    #[serde(default)]
+    pub invite_codes: Vec<SecretString>,
+}
+
+#[derive(Deserialize, Clone, Copy)]
+pub struct AccountSettings {
+    /// Whether a moderator must approve a user's request to wipe their
+    /// account. Moderators' requests always need the admin's approval.
+    #[serde(default)]
+    pub wipe_requires_approval: bool,
+    /// Seconds a password reset link stays valid
+    #[serde(default = "default_password_reset_secs")]
+    pub password_reset_secs: u64,
+}
+
+fn default_password_reset_secs() -> u64 {
+    24 * 60 * 60
+}
+
+impl Default for AccountSettings {
+    fn default() -> Self {
+        Self {
+            wipe_requires_approval: false,
+            password_reset_secs: default_password_reset_secs(),
+        }
+    }
+}
+
+/// Minimum seconds between two topics, posts or replies of a user, by role
+#[derive(Deserialize, Clone, Copy, Debug)]
+pub struct PostingSettings {
+    #[serde(default = "default_author_interval_secs")]
+    pub author_interval_secs: u64,
+    #[serde(default)]
+    pub moderator_interval_secs: u64,
+    #[serde(default)]
+    pub admin_interval_secs: u64,
+}
+
+fn default_author_interval_secs() -> u64 {
+    30
+}
+
+impl Default for PostingSettings {
+    fn default() -> Self {
+        Self {
+            author_interval_secs: default_author_interval_secs(),
+            moderator_interval_secs: 0,
+            admin_interval_secs: 0,
+        }
+    }
+}
+
+/// Throttling of failed logins. Past the free failures, each failure doubles
+/// the wait before the next attempt. Failures older than `window_secs` are
+/// forgotten.
+#[derive(Deserialize, Clone, Debug, Validate)]
+pub struct LoginSettings {
+    /// Failures for a username before backoff starts
+    #[serde(default = "default_backoff_after")]
+    pub backoff_after: u32,
+    /// Failures from an IP address before backoff starts
+    #[serde(default = "default_ip_backoff_after")]
+    pub ip_backoff_after: u32,
+    /// Seconds to wait after the first throttled failure
+    #[serde(default = "default_backoff_base_secs")]
+    #[validate(range(min = 1))]
+    pub backoff_base_secs: u64,
+    #[serde(default = "default_backoff_max_secs")]
+    pub backoff_max_secs: u64,
+    /// Failures for an account that lock it
+    #[serde(default = "default_lockout_after")]
+    #[validate(range(min = 1))]
+    pub lockout_after: u32,
+    /// Seconds until a locked account unlocks itself
+    #[serde(default = "default_lockout_secs")]
+    pub lockout_secs: u64,
+    #[serde(default = "default_window_secs")]
+    #[validate(range(min = 1))]
+    pub window_secs: u64,
+    /// Header carrying the client address when behind a single reverse
+    /// proxy, e.g. `X-Real-IP`. Of a list such as `X-Forwarded-For`, only the
+    /// last entry is used. The peer address is used if unset.
+    pub client_ip_header: Option<String>,
+}
+
+fn default_backoff_after() -> u32 {
+    3
+}
+
+fn default_ip_backoff_after() -> u32 {
+    10
+}
+
+fn default_backoff_base_secs() -> u64 {
+    1
+}
+
+fn default_backoff_max_secs() -> u64 {
+    5 * 60
+}
+
+fn default_lockout_after() -> u32 {
+    10
+}
+
+fn default_lockout_secs() -> u64 {
+    15 * 60
+}
+
+fn default_window_secs() -> u64 {
+    60 * 60
+}
+
+impl Default for LoginSettings {
+    fn default() -> Self {
+        Self {
+            backoff_after: default_backoff_after(),
+            ip_backoff_after: default_ip_backoff_after(),
+            backoff_base_secs: default_backoff_base_secs(),
+            backoff_max_secs: default_backoff_max_secs(),
+            lockout_after: default_lockout_after(),
+            lockout_secs: default_lockout_secs(),
+            window_secs: default_window_secs(),
+            client_ip_header: None,
+        }
+    }
+}
+
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum HashAlgorithm {
+    Argon2d,
+    Argon2i,
+    /// Recommended, resists both side-channel and GPU attacks
+    #[default]
+    Argon2id,
+}
+
+impl From<HashAlgorithm> for argon2::Algorithm {
+    fn from(algorithm: HashAlgorithm) -> Self {
+        match algorithm {
+            HashAlgorithm::Argon2d => argon2::Algorithm::Argon2d,
+            HashAlgorithm::Argon2i => argon2::Algorithm::Argon2i,
+            HashAlgorithm::Argon2id => argon2::Algorithm::Argon2id,
+        }
+    }
+}
+
+/// Argon2 parameters of new password hashes. Existing hashes with weaker
+/// parameters are upgraded when their user logs in.
+#[derive(Deserialize, Clone, Copy, Debug, Validate)]
+#[validate(schema(function = "validate_password_hash"))]
+pub struct PasswordHashSettings {
+    #[serde(default)]
+    pub algorithm: HashAlgorithm,
+    /// Memory cost in KiB
+    #[serde(default = "default_memory_kib")]
+    pub memory_kib: u32,
+    #[serde(default = "default_iterations")]
+    pub iterations: u32,
+    #[serde(default = "default_parallelism")]
+    pub parallelism: u32,
+}
+
+fn default_memory_kib() -> u32 {
+    argon2::Params::DEFAULT_M_COST
+}
+
+fn default_iterations() -> u32 {
+    argon2::Params::DEFAULT_T_COST
+}
+
+fn default_parallelism() -> u32 {
+    argon2::Params::DEFAULT_P_COST
+}
+
+impl Default for PasswordHashSettings {
+    fn default() -> Self {
+        Self {
+            algorithm: HashAlgorithm::default(),
+            memory_kib: default_memory_kib(),
+            iterations: default_iterations(),
+            parallelism: default_parallelism(),
+        }
+    }
+}
+
+impl PasswordHashSettings {
+    pub fn params(&self) -> Result<argon2::Params, argon2::Error> {
+        argon2::Params::new(self.memory_kib, self.iterations, self.parallelism, None)
+    }
+}
+
+/// Rejects parameters that Argon2 itself does not accept, e.g. less than 8 KiB
+/// of memory per lane
+fn validate_password_hash(settings: &PasswordHashSettings) -> Result<(), ValidationError> {
+    settings
+        .params()
+        .map(|_| ())
+        .map_err(|_| ValidationError::new("argon2_params"))
+}
+
+/// Minimum length of a session secret, as required by `axum_sessions`
+pub const SESSION_SECRET_LENGTH: usize = 64;
+
+#[derive(Error, Debug)]
+pub enum SessionSecretError {
+    #[error("session secret is not valid base64")]
+    Base64(#[from] base64::DecodeError),
+    #[error("session secret must be at least {SESSION_SECRET_LENGTH} bytes, got {0}")]
+    TooShort(usize),
+}
+
+/// Base64 encoded session signing secrets
+#[derive(Deserialize, Default)]
+pub struct SessionSecretSettings {
+    /// Secret used to sign new cookies
+    pub key: Option<SecretString>,
+    /// Retired secrets, only used to verify existing cookies
+    #[serde(default)]
+    pub old_keys: Vec<SecretString>,
+}
+
+/// Decoded session signing secrets
+pub struct SessionSecrets {
+    pub key: Secret<Vec<u8>>,
+    pub old_keys: Vec<Secret<Vec<u8>>>,
+}
+
+impl SessionSecretSettings {
+    /// Decodes the configured secrets, generating a temporary key if none is configured
+    pub fn secrets(&self) -> Result<SessionSecrets, SessionSecretError> {
+        let key = if let Some(key) = &self.key {
+            decode_secret(key)?
+        } else {
+            tracing::warn!(
+                "No session secret configured, generating a random one. \
+                Sessions will not survive a restart."
+            );
+            Secret::new(rand::thread_rng().gen::<[u8; 128]>().to_vec())
+        };
+        let old_keys = self
+            .old_keys
+            .iter()
+            .map(decode_secret)
+            .collect::<Result<_, _>>()?;
+        Ok(SessionSecrets { key, old_keys })
+    }
+}
+
+fn decode_secret(secret: &SecretString) -> Result<Secret<Vec<u8>>, SessionSecretError> {
+    let secret = STANDARD.decode(secret.expose_secret().trim())?;
+    if secret.len() < SESSION_SECRET_LENGTH {
+        return Err(SessionSecretError::TooShort(secret.len()));
+    }
+    Ok(Secret::new(secret))
+}
+
+/// Deployment profile, selecting `<profile>.toml` next to the base configuration
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Environment {
+    Local,
+    Production,
+}
+
+impl Environment {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Environment::Local => "local",
+            Environment::Production => "production",
+        }
+    }
+}
+
+impl std::str::FromStr for Environment {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "local" => Ok(Self::Local),
+            "production" => Ok(Self::Production),
+            other => Err(format!(
+                "`{}` is not a supported environment, use `local` or `production`",
+                other
+            )),
+        }
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum ConfigurationError {
+    #[error(transparent)]
+    ConfigError(#[from] config::ConfigError),
+    #[error("invalid configuration: {0}")]
+    Invalid(#[from] ValidationErrors),
+}
+
+/// Reads the configuration in layers, each overriding the previous one:
+///
+/// 1. the base file at `path`
+/// 2. the optional `<environment>.toml` in the same directory
+/// 3. `REFORUM__<SECTION>__<KEY>` environment variables
+pub fn get_configuration(
+    path: &Path,
+    environment: Environment,
+) -> Result<Settings, ConfigurationError> {
+    let environment_file = path
+        .with_file_name(environment.as_str())
+        .with_extension("toml");
     let settings = config::Config::builder()
-        .add_source(config::File::new(
-            "configuration.toml",
-            config::FileFormat::Toml,
-        ))
+        .add_source(config::File::from(path).format(config::FileFormat::Toml))
+        .add_source(
+            config::File::from(environment_file)
+                .format(config::FileFormat::Toml)
+                .required(false),
+        )
+        .add_source(
+            config::Environment::with_prefix("REFORUM")
+                .separator("__")
+                .list_separator(",")
+                .with_list_parse_key("session_secret.old_keys")
+                .with_list_parse_key("registration.invite_codes")
+                .try_parsing(true),
+        )
         .build()?;
-    settings.try_deserialize()
+    let settings: Settings = settings.try_deserialize()?;
+    settings.validate()?;
+    Ok(settings)
 }
@@ -1,4 +1,5 @@
 pub mod auth;
+pub mod cli;
 pub mod configuration;
 pub mod error;
 pub mod model;
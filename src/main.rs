@@ -1,8 +1,8 @@
 use color_eyre::*;
-use reforum::startup::run;
+use reforum::cli;
 
 #[tokio::main]
 async fn main() -> Result<()> {
-    run().await?;
+    cli::run().await?;
     Ok(())
 }
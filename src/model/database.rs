@@ -0,0 +1,134 @@
+use std::sync::{Arc, Mutex};
+
+use rusqlite::{Connection, Transaction, TransactionBehavior};
+use thiserror::Error;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+use crate::configuration::SQLite3Settings;
+use crate::telemetry::spawn_blocking_with_tracing;
+
+#[derive(Error, Debug)]
+pub enum InteractError {
+    #[error("connection pool is closed")]
+    PoolClosed,
+    #[error("database task panicked or was cancelled")]
+    TaskFailed,
+}
+
+/// A fixed-size pool of SQLite connections.
+///
+/// Queries are run on blocking threads through [`Database::interact`], so
+/// they never block the async runtime.
+#[derive(Clone, Debug)]
+pub struct Database {
+    inner: Arc<Inner>,
+}
+
+#[derive(Debug)]
+struct Inner {
+    connections: Mutex<Vec<Connection>>,
+    semaphore: Arc<Semaphore>,
+}
+
+/// A connection checked out of the pool, returned on drop
+struct PooledConnection {
+    conn: Option<Connection>,
+    inner: Arc<Inner>,
+    _permit: OwnedSemaphorePermit,
+}
+
+impl Drop for PooledConnection {
+    fn drop(&mut self) {
+        if let Some(conn) = self.conn.take() {
+            self.inner.connections.lock().unwrap().push(conn);
+        }
+    }
+}
+
+impl Database {
+    /// Opens `settings.pool_size` connections
+    pub fn new(settings: &SQLite3Settings) -> Result<Self, rusqlite::Error> {
+        let size = settings.pool_size.max(1);
+        let connections = (0..size)
+            .map(|_| settings.connect())
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(Self {
+            inner: Arc::new(Inner {
+                connections: Mutex::new(connections),
+                semaphore: Arc::new(Semaphore::new(size)),
+            }),
+        })
+    }
+
+    async fn get(&self) -> Result<PooledConnection, InteractError> {
+        let permit = self
+            .inner
+            .semaphore
+            .clone()
+            .acquire_owned()
+            .await
+            .map_err(|_| InteractError::PoolClosed)?;
+        let conn = self
+            .inner
+            .connections
+            .lock()
+            .unwrap()
+            .pop()
+            .ok_or(InteractError::PoolClosed)?;
+        Ok(PooledConnection {
+            conn: Some(conn),
+            inner: self.inner.clone(),
+            _permit: permit,
+        })
+    }
+
+    /// Runs `f` with a pooled connection on a blocking thread
+    pub async fn interact<F, R>(&self, f: F) -> Result<R, InteractError>
+    where
+        F: FnOnce(&mut Connection) -> R + Send + 'static,
+        R: Send + 'static,
+    {
+        let mut pooled = self.get().await?;
+        spawn_blocking_with_tracing(move || {
+            let conn = pooled.conn.as_mut().expect("pooled connection");
+            f(conn)
+        })
+        .await
+        .map_err(|_| InteractError::TaskFailed)
+    }
+
+    /// Runs `f` inside a transaction, which is committed if `f` returns `Ok`
+    /// and rolled back otherwise
+    pub async fn transaction<F, R, E>(&self, f: F) -> Result<Result<R, E>, InteractError>
+    where
+        F: FnOnce(&Transaction) -> Result<R, E> + Send + 'static,
+        R: Send + 'static,
+        E: From<rusqlite::Error> + Send + 'static,
+    {
+        self.transaction_with_behavior(TransactionBehavior::Deferred, f)
+            .await
+    }
+
+    /// Like [`Database::transaction`], but with the given locking behavior.
+    /// Transactions that read a row and then write based on it should be
+    /// `Immediate`: a deferred one fails with `SQLITE_BUSY` when it cannot
+    /// upgrade its read lock, instead of waiting for the busy timeout.
+    pub async fn transaction_with_behavior<F, R, E>(
+        &self,
+        behavior: TransactionBehavior,
+        f: F,
+    ) -> Result<Result<R, E>, InteractError>
+    where
+        F: FnOnce(&Transaction) -> Result<R, E> + Send + 'static,
+        R: Send + 'static,
+        E: From<rusqlite::Error> + Send + 'static,
+    {
+        self.interact(move |conn| {
+            let tx = conn.transaction_with_behavior(behavior)?;
+            let result = f(&tx)?;
+            tx.commit()?;
+            Ok(result)
+        })
+        .await
+    }
+}
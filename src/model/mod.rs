@@ -1,4 +1,12 @@
+pub mod database;
 pub mod from_row;
-// pub mod post;
-// pub mod topic;
+pub mod moderation;
+pub mod moderation_log;
+pub mod moderator;
+pub mod post;
+pub mod post_interval;
+pub mod reply;
+pub mod revision;
+pub mod topic;
 pub mod user;
+pub mod wipe;
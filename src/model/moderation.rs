@@ -0,0 +1,170 @@
+use axum::response::{IntoResponse, Response};
+use chrono::{DateTime, Duration, Utc};
+use hyper::StatusCode;
+use rusqlite::{params, Connection, OptionalExtension};
+use thiserror::*;
+
+use crate::auth::extractor::UserAuth;
+use crate::model::database::{Database, InteractError};
+use crate::model::moderation_log::{LogRecord, ModerationAction};
+
+/// Ban and mute state of a user, with the reasons given by the moderator
+#[derive(Debug)]
+pub struct UserStatus {
+    pub user_id: i64,
+    pub username: String,
+    pub is_admin: bool,
+    /// Read-only regardless of mutes
+    pub is_viewer: bool,
+    pub is_moderator: bool,
+    pub banned_at: Option<DateTime<Utc>>,
+    pub ban_reason: Option<String>,
+    pub muted_until: Option<DateTime<Utc>>,
+    pub mute_reason: Option<String>,
+}
+
+/// A moderator action on a user
+#[derive(Debug, Clone, Copy)]
+pub enum Sanction {
+    Ban,
+    Unban,
+    Mute(Duration),
+    Unmute,
+}
+
+#[derive(Error, Debug)]
+pub enum ModerationError {
+    #[error("user `{0}` not found")]
+    NotFound(i64),
+    #[error("forbidden: {0}")]
+    Forbidden(String),
+    #[error("a reason is required")]
+    MissingReason,
+    #[error(transparent)]
+    RusqliteError(#[from] rusqlite::Error),
+    #[error(transparent)]
+    InteractError(#[from] InteractError),
+}
+
+impl IntoResponse for ModerationError {
+    fn into_response(self) -> Response {
+        match self {
+            ModerationError::NotFound(_) => (StatusCode::NOT_FOUND, "404 not found"),
+            ModerationError::Forbidden(_) => (StatusCode::FORBIDDEN, "403 forbidden"),
+            ModerationError::MissingReason => (StatusCode::BAD_REQUEST, "A reason is required"),
+            ModerationError::RusqliteError(_) => (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "500 Internal Server Error",
+            ),
+            ModerationError::InteractError(_) => (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "500 Internal Server Error",
+            ),
+        }
+        .into_response()
+    }
+}
+
+type Result<T, E = ModerationError> = std::result::Result<T, E>;
+
+impl UserStatus {
+    pub fn query(conn: &Connection, user_id: i64) -> Result<Option<UserStatus>, rusqlite::Error> {
+        conn.query_row(
+            r#"
+            SELECT
+                u.id, u.username, u.role = 'admin' is_admin, u.role = 'viewer' is_viewer,
+                m.moderator_user_id IS NOT NULL is_moderator,
+                u.banned_at, u.ban_reason, u.muted_until, u.mute_reason
+            FROM users u LEFT JOIN moderators m ON m.moderator_user_id = u.id
+            WHERE u.id = ? AND u.wiped_at IS NULL
+            "#,
+            [user_id],
+            |row| {
+                Ok(UserStatus {
+                    user_id: row.get("id")?,
+                    username: row.get("username")?,
+                    is_admin: row.get("is_admin")?,
+                    is_viewer: row.get("is_viewer")?,
+                    is_moderator: row.get("is_moderator")?,
+                    banned_at: row.get("banned_at")?,
+                    ban_reason: row.get("ban_reason")?,
+                    muted_until: row.get("muted_until")?,
+                    mute_reason: row.get("mute_reason")?,
+                })
+            },
+        )
+        .optional()
+    }
+    pub fn is_banned(&self) -> bool {
+        self.banned_at.map(|b| b < Utc::now()) == Some(true)
+    }
+    pub fn is_muted(&self) -> bool {
+        self.muted_until.map(|m| Utc::now() < m) == Some(true)
+    }
+}
+
+impl Sanction {
+    /// Applies the sanction to `user_id` on behalf of `auth`. Moderators may
+    /// sanction regular users, and only the admin may sanction moderators.
+    pub async fn apply(
+        self,
+        db: &Database,
+        auth: &UserAuth,
+        user_id: i64,
+        reason: &str,
+    ) -> Result<()> {
+        let reason = reason.trim().to_owned();
+        if reason.is_empty() {
+            return Err(ModerationError::MissingReason);
+        }
+        if !(auth.is_moderator() || auth.is_admin()) {
+            return Err(ModerationError::Forbidden(format!(
+                "user {} is not a moderator",
+                auth.id
+            )));
+        }
+        let admin = auth.is_admin();
+        let moderator_id = auth.id;
+        db.interact(move |conn| -> Result<()> {
+            let tx = conn.transaction()?;
+            let target =
+                UserStatus::query(&tx, user_id)?.ok_or(ModerationError::NotFound(user_id))?;
+            if target.is_admin || user_id == moderator_id || (target.is_moderator && !admin) {
+                return Err(ModerationError::Forbidden(format!(
+                    "user {} cannot sanction user {}",
+                    moderator_id, user_id
+                )));
+            }
+            let action = match self {
+                Sanction::Ban => ModerationAction::Ban,
+                Sanction::Unban => ModerationAction::Unban,
+                Sanction::Mute(_) => ModerationAction::Mute,
+                Sanction::Unmute => ModerationAction::Unmute,
+            };
+            LogRecord::new(Some(moderator_id), action, user_id)
+                .reason(&reason)
+                .insert(&tx)?;
+            match self {
+                Sanction::Ban => tx.execute(
+                    r#"UPDATE users SET banned_at = ?, ban_reason = ? WHERE id = ?"#,
+                    params![Utc::now(), reason, user_id],
+                )?,
+                Sanction::Unban => tx.execute(
+                    r#"UPDATE users SET banned_at = NULL, ban_reason = NULL WHERE id = ?"#,
+                    [user_id],
+                )?,
+                Sanction::Mute(duration) => tx.execute(
+                    r#"UPDATE users SET muted_until = ?, mute_reason = ? WHERE id = ?"#,
+                    params![Utc::now() + duration, reason, user_id],
+                )?,
+                Sanction::Unmute => tx.execute(
+                    r#"UPDATE users SET muted_until = NULL, mute_reason = NULL WHERE id = ?"#,
+                    [user_id],
+                )?,
+            };
+            tx.commit()?;
+            Ok(())
+        })
+        .await?
+    }
+}
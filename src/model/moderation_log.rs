@@ -0,0 +1,235 @@
+use std::str::FromStr;
+
+use chrono::{DateTime, Utc};
+use rusqlite::{params, Connection};
+use serde::{Deserialize, Serialize};
+
+/// Kinds of privileged actions recorded in the moderation log
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ModerationAction {
+    EditTopic,
+    DeleteTopic,
+    RestoreTopic,
+    EditPost,
+    DeletePost,
+    RestorePost,
+    DeleteReply,
+    Ban,
+    Unban,
+    Mute,
+    Unmute,
+    Promote,
+    Demote,
+    SetRole,
+    WipeAccount,
+    RejectWipe,
+    IssuePasswordReset,
+}
+
+impl ModerationAction {
+    pub const ALL: [ModerationAction; 17] = [
+        Self::EditTopic,
+        Self::DeleteTopic,
+        Self::RestoreTopic,
+        Self::EditPost,
+        Self::DeletePost,
+        Self::RestorePost,
+        Self::DeleteReply,
+        Self::Ban,
+        Self::Unban,
+        Self::Mute,
+        Self::Unmute,
+        Self::Promote,
+        Self::Demote,
+        Self::SetRole,
+        Self::WipeAccount,
+        Self::RejectWipe,
+        Self::IssuePasswordReset,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::EditTopic => "edit_topic",
+            Self::DeleteTopic => "delete_topic",
+            Self::RestoreTopic => "restore_topic",
+            Self::EditPost => "edit_post",
+            Self::DeletePost => "delete_post",
+            Self::RestorePost => "restore_post",
+            Self::DeleteReply => "delete_reply",
+            Self::Ban => "ban",
+            Self::Unban => "unban",
+            Self::Mute => "mute",
+            Self::Unmute => "unmute",
+            Self::Promote => "promote",
+            Self::Demote => "demote",
+            Self::SetRole => "set_role",
+            Self::WipeAccount => "wipe_account",
+            Self::RejectWipe => "reject_wipe",
+            Self::IssuePasswordReset => "issue_password_reset",
+        }
+    }
+}
+
+impl FromStr for ModerationAction {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .into_iter()
+            .find(|a| a.as_str() == s)
+            .ok_or_else(|| format!("unknown moderation action `{}`", s))
+    }
+}
+
+/// An entry about to be appended to the moderation log
+#[derive(Debug)]
+pub struct LogRecord<'a> {
+    moderator_user_id: Option<i64>,
+    action: ModerationAction,
+    target_user_id: Option<i64>,
+    topic_id: Option<i64>,
+    post_id: Option<i64>,
+    reply_id: Option<i64>,
+    reason: Option<&'a str>,
+}
+
+impl<'a> LogRecord<'a> {
+    /// `moderator_user_id` is `None` for actions run from the command line
+    pub fn new(
+        moderator_user_id: Option<i64>,
+        action: ModerationAction,
+        target_user_id: i64,
+    ) -> Self {
+        Self {
+            moderator_user_id,
+            action,
+            target_user_id: Some(target_user_id),
+            topic_id: None,
+            post_id: None,
+            reply_id: None,
+            reason: None,
+        }
+    }
+    pub fn topic(mut self, topic_id: i64) -> Self {
+        self.topic_id = Some(topic_id);
+        self
+    }
+    pub fn post(mut self, post_id: i64) -> Self {
+        self.post_id = Some(post_id);
+        self
+    }
+    pub fn reply(mut self, reply_id: i64) -> Self {
+        self.reply_id = Some(reply_id);
+        self
+    }
+    pub fn reason(mut self, reason: &'a str) -> Self {
+        self.reason = Some(reason);
+        self
+    }
+    /// Appends the entry. Run it in the same transaction as the action.
+    pub fn insert(&self, conn: &Connection) -> Result<(), rusqlite::Error> {
+        conn.execute(
+            r#"
+            INSERT INTO moderation_log(
+                moderator_user_id, action, target_user_id, topic_id, post_id, reply_id, reason
+            )
+            VALUES (?, ?, ?, ?, ?, ?, ?)
+            "#,
+            params![
+                self.moderator_user_id,
+                self.action.as_str(),
+                self.target_user_id,
+                self.topic_id,
+                self.post_id,
+                self.reply_id,
+                self.reason,
+            ],
+        )?;
+        Ok(())
+    }
+}
+
+/// A recorded entry, with the usernames of the users involved
+#[derive(Debug)]
+pub struct LogEntry {
+    pub id: i64,
+    pub moderator_user_id: Option<i64>,
+    pub moderator_username: Option<String>,
+    pub action: ModerationAction,
+    pub target_user_id: Option<i64>,
+    pub target_username: Option<String>,
+    pub topic_id: Option<i64>,
+    pub post_id: Option<i64>,
+    pub reply_id: Option<i64>,
+    pub reason: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+/// Restricts which entries are listed. Unset fields match everything.
+#[derive(Debug, Default)]
+pub struct LogFilter {
+    pub moderator_user_id: Option<i64>,
+    pub target_user_id: Option<i64>,
+    pub action: Option<ModerationAction>,
+    /// Only entries older than this ID
+    pub before_id: Option<i64>,
+}
+
+impl LogEntry {
+    /// Entries matching `filter`, newest first
+    pub fn query(
+        conn: &Connection,
+        filter: &LogFilter,
+        limit: usize,
+    ) -> Result<Vec<LogEntry>, rusqlite::Error> {
+        let mut stmt = conn.prepare(
+            r#"
+            SELECT l.*, m.username moderator_username, t.username target_username
+            FROM
+                moderation_log l
+                LEFT JOIN users m ON m.id = l.moderator_user_id
+                LEFT JOIN users t ON t.id = l.target_user_id
+            WHERE
+                (:moderator IS NULL OR l.moderator_user_id = :moderator)
+                AND (:target IS NULL OR l.target_user_id = :target)
+                AND (:action IS NULL OR l.action = :action)
+                AND (:before_id IS NULL OR l.id < :before_id)
+            ORDER BY l.id DESC
+            LIMIT :limit
+            "#,
+        )?;
+        let entries = stmt.query_map(
+            rusqlite::named_params! {
+                ":moderator": filter.moderator_user_id,
+                ":target": filter.target_user_id,
+                ":action": filter.action.map(|a| a.as_str()),
+                ":before_id": filter.before_id,
+                ":limit": limit as i64,
+            },
+            |row| {
+                let action: String = row.get("action")?;
+                Ok(LogEntry {
+                    id: row.get("id")?,
+                    moderator_user_id: row.get("moderator_user_id")?,
+                    moderator_username: row.get("moderator_username")?,
+                    action: action.parse().map_err(|e: String| {
+                        rusqlite::Error::FromSqlConversionFailure(
+                            0,
+                            rusqlite::types::Type::Text,
+                            e.into(),
+                        )
+                    })?,
+                    target_user_id: row.get("target_user_id")?,
+                    target_username: row.get("target_username")?,
+                    topic_id: row.get("topic_id")?,
+                    post_id: row.get("post_id")?,
+                    reply_id: row.get("reply_id")?,
+                    reason: row.get("reason")?,
+                    created_at: row.get("created_at")?,
+                })
+            },
+        )?;
+        entries.collect()
+    }
+}
@@ -0,0 +1,204 @@
+use axum::response::{IntoResponse, Response};
+use chrono::{DateTime, Utc};
+use hyper::StatusCode;
+use rusqlite::{Connection, OptionalExtension};
+use thiserror::*;
+
+use crate::model::database::InteractError;
+use crate::model::moderation_log::{LogRecord, ModerationAction};
+
+/// A current moderator
+#[derive(Debug)]
+pub struct Moderator {
+    pub user_id: i64,
+    pub username: String,
+    pub assigned_at: DateTime<Utc>,
+}
+
+/// A former moderator, kept even after their account is wiped
+#[derive(Debug)]
+pub struct PastModerator {
+    pub id: i64,
+    pub user_id: i64,
+    pub username: String,
+    pub unassigned_at: DateTime<Utc>,
+    pub reason: String,
+}
+
+#[derive(Error, Debug)]
+pub enum ModeratorError {
+    #[error("user `{0}` not found")]
+    NotFound(i64),
+    #[error("no user named `{0}`")]
+    UnknownUsername(String),
+    #[error("user `{0}` is already a moderator")]
+    AlreadyModerator(i64),
+    #[error("user `{0}` is not a moderator")]
+    NotModerator(i64),
+    #[error("forbidden: {0}")]
+    Forbidden(String),
+    #[error("a reason is required")]
+    MissingReason,
+    #[error(transparent)]
+    RusqliteError(#[from] rusqlite::Error),
+    #[error(transparent)]
+    InteractError(#[from] InteractError),
+}
+
+impl IntoResponse for ModeratorError {
+    fn into_response(self) -> Response {
+        match self {
+            ModeratorError::NotFound(_) | ModeratorError::UnknownUsername(_) => {
+                (StatusCode::NOT_FOUND, "404 not found")
+            }
+            ModeratorError::AlreadyModerator(_) => {
+                (StatusCode::CONFLICT, "User is already a moderator")
+            }
+            ModeratorError::NotModerator(_) => (StatusCode::CONFLICT, "User is not a moderator"),
+            ModeratorError::Forbidden(_) => (StatusCode::FORBIDDEN, "403 forbidden"),
+            ModeratorError::MissingReason => (StatusCode::BAD_REQUEST, "A reason is required"),
+            ModeratorError::RusqliteError(_) => (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "500 Internal Server Error",
+            ),
+            ModeratorError::InteractError(_) => (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "500 Internal Server Error",
+            ),
+        }
+        .into_response()
+    }
+}
+
+type Result<T, E = ModeratorError> = std::result::Result<T, E>;
+
+impl Moderator {
+    /// Makes a user a moderator. `admin_user_id` is `None` on the command line.
+    pub fn promote(conn: &mut Connection, admin_user_id: Option<i64>, user_id: i64) -> Result<()> {
+        let tx = conn.transaction()?;
+        check_assignable(&tx, user_id)?;
+        if !Self::insert(&tx, user_id)? {
+            return Err(ModeratorError::AlreadyModerator(user_id));
+        }
+        LogRecord::new(admin_user_id, ModerationAction::Promote, user_id).insert(&tx)?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// Revokes moderation from a user, recording `reason` in `past_moderators`.
+    /// `admin_user_id` is `None` on the command line.
+    pub fn demote(
+        conn: &mut Connection,
+        admin_user_id: Option<i64>,
+        user_id: i64,
+        reason: &str,
+    ) -> Result<()> {
+        let reason = reason.trim();
+        if reason.is_empty() {
+            return Err(ModeratorError::MissingReason);
+        }
+        let tx = conn.transaction()?;
+        check_assignable(&tx, user_id)?;
+        if !Self::remove(&tx, user_id, reason)? {
+            return Err(ModeratorError::NotModerator(user_id));
+        }
+        LogRecord::new(admin_user_id, ModerationAction::Demote, user_id)
+            .reason(reason)
+            .insert(&tx)?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// Inserts the `moderators` row, returning whether there was none.
+    /// Runs inside the caller's transaction.
+    pub(crate) fn insert(conn: &Connection, user_id: i64) -> Result<bool, rusqlite::Error> {
+        Ok(conn.execute(
+            r#"INSERT OR IGNORE INTO moderators(moderator_user_id) VALUES (?)"#,
+            [user_id],
+        )? > 0)
+    }
+
+    /// Moves the `moderators` row to `past_moderators`, returning whether
+    /// there was one. Runs inside the caller's transaction.
+    pub(crate) fn remove(
+        conn: &Connection,
+        user_id: i64,
+        reason: &str,
+    ) -> Result<bool, rusqlite::Error> {
+        let demoted = conn.execute(
+            r#"DELETE FROM moderators WHERE moderator_user_id = ?"#,
+            [user_id],
+        )?;
+        if demoted > 0 {
+            conn.execute(
+                r#"INSERT INTO past_moderators(moderator_user_id, reason) VALUES (?, ?)"#,
+                rusqlite::params![user_id, reason],
+            )?;
+        }
+        Ok(demoted > 0)
+    }
+
+    /// Current moderators, longest serving first
+    pub fn list(conn: &Connection) -> Result<Vec<Moderator>, rusqlite::Error> {
+        let mut stmt = conn.prepare(
+            r#"
+            SELECT m.moderator_user_id, u.username, m.assigned_at
+            FROM moderators m JOIN users u ON u.id = m.moderator_user_id
+            ORDER BY m.assigned_at, m.moderator_user_id
+            "#,
+        )?;
+        let moderators = stmt.query_map([], |row| {
+            Ok(Moderator {
+                user_id: row.get(0)?,
+                username: row.get(1)?,
+                assigned_at: row.get(2)?,
+            })
+        })?;
+        moderators.collect()
+    }
+}
+
+impl PastModerator {
+    /// Every demotion, most recent first
+    pub fn list(conn: &Connection) -> Result<Vec<PastModerator>, rusqlite::Error> {
+        let mut stmt = conn.prepare(
+            r#"
+            SELECT p.id, p.moderator_user_id, u.username, p.unassigned_at, p.reason
+            FROM past_moderators p JOIN users u ON u.id = p.moderator_user_id
+            ORDER BY p.id DESC
+            "#,
+        )?;
+        let past = stmt.query_map([], |row| {
+            Ok(PastModerator {
+                id: row.get(0)?,
+                user_id: row.get(1)?,
+                username: row.get(2)?,
+                unassigned_at: row.get(3)?,
+                reason: row.get(4)?,
+            })
+        })?;
+        past.collect()
+    }
+}
+
+/// Admins and wiped accounts cannot be promoted or demoted
+fn check_assignable(conn: &Connection, user_id: i64) -> Result<()> {
+    let status: Option<(bool, bool)> = conn
+        .query_row(
+            r#"SELECT role = 'admin', wiped_at IS NOT NULL FROM users WHERE id = ?"#,
+            [user_id],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )
+        .optional()?;
+    match status {
+        None => Err(ModeratorError::NotFound(user_id)),
+        Some((true, _)) => Err(ModeratorError::Forbidden(
+            "an admin cannot be a moderator".to_owned(),
+        )),
+        Some((_, true)) => Err(ModeratorError::Forbidden(format!(
+            "user {} has been wiped",
+            user_id
+        ))),
+        Some((false, false)) => Ok(()),
+    }
+}
@@ -1,11 +1,23 @@
+use std::ops::Range;
+
+use axum::response::{IntoResponse, Response};
+use hyper::StatusCode;
 use thiserror::*;
 
-use axum_sessions::extractors::ReadableSession;
 use chrono::{DateTime, Utc};
-use rusqlite::Connection;
+use rusqlite::{params, TransactionBehavior};
 
-use crate::model::from_row::FromRow;
+use crate::auth::extractor::UserAuth;
+use crate::configuration::PostingSettings;
+use crate::model::{
+    database::{Database, InteractError},
+    from_row::FromRow,
+    moderation_log::{LogRecord, ModerationAction},
+    post_interval,
+    topic::Topic,
+};
 
+#[derive(Debug)]
 pub struct Post {
     pub id: i64,
     pub topic_id: i64,
@@ -20,28 +32,252 @@ pub struct Post {
 }
 
 #[derive(Error, Debug)]
-pub enum PostError {}
+pub enum PostError {
+    #[error("post `{0}` not found")]
+    NotFound(i64),
+    #[error("forbidden: {0}")]
+    Forbidden(String),
+    #[error(transparent)]
+    RusqliteError(#[from] rusqlite::Error),
+    #[error(transparent)]
+    InteractError(#[from] InteractError),
+    #[error("posting again too soon, retry at {0}")]
+    TooSoon(DateTime<Utc>),
+}
+
+impl IntoResponse for PostError {
+    fn into_response(self) -> Response {
+        match self {
+            PostError::NotFound(_) => (StatusCode::NOT_FOUND, "404 not found"),
+            PostError::Forbidden(_) => (StatusCode::FORBIDDEN, "403 forbidden"),
+            PostError::TooSoon(_) => (StatusCode::TOO_MANY_REQUESTS, "429 too many requests"),
+            PostError::RusqliteError(_) => (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "500 Internal Server Error",
+            ),
+            PostError::InteractError(_) => (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "500 Internal Server Error",
+            ),
+        }
+        .into_response()
+    }
+}
 
 type Result<T, E = PostError> = std::result::Result<T, E>;
 
 impl Post {
+    /// Queries a post visible to the viewer. Does not check the topic itself.
+    pub async fn query(db: &Database, auth: Option<&UserAuth>, id: i64) -> Result<Post> {
+        let post = db
+            .interact(move |conn| {
+                conn.query_row(
+                    r#"SELECT * FROM posts WHERE id = ?"#,
+                    [id],
+                    Post::try_from_row,
+                )
+            })
+            .await?
+            .map_err(|e| match e {
+                rusqlite::Error::QueryReturnedNoRows => PostError::NotFound(id),
+                e => e.into(),
+            })?;
+        if post.is_visible_to(auth) {
+            Ok(post)
+        } else {
+            Err(PostError::Forbidden(format!("cannot view post {}", id)))
+        }
+    }
+    /// Queries the posts of a topic numbered within `post_numbers`, in order,
+    /// keeping only those visible to the viewer. Does not check the topic itself.
     pub async fn query_by_topic_id(
-        _db: &Connection,
-        _session: &ReadableSession,
-        _topic_id: i64,
+        db: &Database,
+        auth: Option<&UserAuth>,
+        topic_id: i64,
+        post_numbers: Range<i64>,
     ) -> Result<Vec<Post>> {
-        todo!()
+        let posts = db
+            .interact(move |conn| -> Result<Vec<Post>, rusqlite::Error> {
+                let mut stmt = conn.prepare(
+                    r#"
+                    SELECT * FROM posts
+                    WHERE topic_id = ? AND post_number >= ? AND post_number < ?
+                    ORDER BY post_number
+                    "#,
+                )?;
+                let posts = stmt.query_map(
+                    params![topic_id, post_numbers.start, post_numbers.end],
+                    Post::try_from_row,
+                )?;
+                posts.collect()
+            })
+            .await??;
+        Ok(posts
+            .into_iter()
+            .filter(|post| post.is_visible_to(auth))
+            .collect())
+    }
+    /// Appends a public post to `topic`, which the caller has already checked
+    /// is visible to `auth`
+    pub async fn insert(
+        db: &Database,
+        auth: &UserAuth,
+        settings: PostingSettings,
+        topic: &Topic,
+        body: &str,
+    ) -> Result<Post> {
+        if !auth.can_post() {
+            return Err(PostError::Forbidden(format!(
+                "user {} ({:?}) cannot post",
+                auth.id, auth.role
+            )));
+        }
+        if topic.deleted_at.is_some() {
+            return Err(PostError::Forbidden(format!(
+                "topic {} is deleted",
+                topic.id
+            )));
+        }
+        let user_id = auth.id;
+        let interval = post_interval::interval(settings, auth.role);
+        let topic_id = topic.id;
+        let body = body.to_owned();
+        let post = db
+            .transaction_with_behavior(TransactionBehavior::Immediate, move |tx| {
+                if let Some(retry_at) = post_interval::claim(tx, user_id, interval)? {
+                    return Err(PostError::TooSoon(retry_at));
+                }
+                Ok(tx.query_row(
+                    r#"
+                    INSERT INTO posts(topic_id, author_user_id, body, post_number)
+                    VALUES (?1, ?2, ?3, (SELECT next_post_number FROM topics WHERE id = ?1))
+                    RETURNING *
+                    "#,
+                    params![topic_id, user_id, body],
+                    Post::try_from_row,
+                )?)
+            })
+            .await??;
+        Ok(post)
+    }
+    /// Changes the body, keeping the previous one as a revision. The caller
+    /// has already checked that the post and `topic` are visible to `auth`.
+    pub async fn edit(
+        self,
+        db: &Database,
+        auth: &UserAuth,
+        topic: &Topic,
+        body: &str,
+    ) -> Result<Post> {
+        if topic.deleted_at.is_some()
+            || self.deleted_at.is_some()
+            || !auth.can_edit(self.author_user_id)
+        {
+            return Err(PostError::Forbidden(format!(
+                "user {} ({:?}) cannot edit post {}",
+                auth.id, auth.role, self.id
+            )));
+        }
+        if self.body == body {
+            return Ok(self);
+        }
+        let id = self.id;
+        let topic_id = self.topic_id;
+        let author_user_id = self.author_user_id;
+        let user_id = auth.id;
+        let body = body.to_owned();
+        let post = db
+            .transaction(move |tx| {
+                tx.execute(
+                    r#"
+                    INSERT INTO revisions(post_id, content, edited_by)
+                    SELECT id, body, ? FROM posts WHERE id = ?
+                    "#,
+                    params![user_id, id],
+                )?;
+                if user_id != author_user_id {
+                    LogRecord::new(Some(user_id), ModerationAction::EditPost, author_user_id)
+                        .topic(topic_id)
+                        .post(id)
+                        .insert(tx)?;
+                }
+                tx.query_row(
+                    r#"
+                    UPDATE posts SET body = ?, last_updated_by = ?
+                    WHERE id = ?
+                    RETURNING *
+                    "#,
+                    params![body, user_id, id],
+                    Post::try_from_row,
+                )
+            })
+            .await??;
+        Ok(post)
+    }
+    /// Soft-deletes the post. Its author, moderators and the admin may
+    /// delete it.
+    pub async fn delete(self, db: &Database, auth: &UserAuth) -> Result<Post> {
+        if self.deleted_at.is_some() || !auth.can_edit(self.author_user_id) {
+            return Err(PostError::Forbidden(format!(
+                "user {} ({:?}) cannot delete post {}",
+                auth.id, auth.role, self.id
+            )));
+        }
+        self.set_deleted_at(db, auth, Some(Utc::now())).await
+    }
+    /// Undoes a soft-deletion. Only moderators and the admin may restore.
+    pub async fn restore(self, db: &Database, auth: &UserAuth) -> Result<Post> {
+        if self.deleted_at.is_none() || !(auth.is_moderator() || auth.is_admin()) {
+            return Err(PostError::Forbidden(format!(
+                "user {} ({:?}) cannot restore post {}",
+                auth.id, auth.role, self.id
+            )));
+        }
+        self.set_deleted_at(db, auth, None).await
+    }
+    /// `number_posts` of the topic is kept in sync by a trigger. Deleting
+    /// someone else's post and restoring are logged.
+    async fn set_deleted_at(
+        self,
+        db: &Database,
+        auth: &UserAuth,
+        deleted_at: Option<DateTime<Utc>>,
+    ) -> Result<Post> {
+        let id = self.id;
+        let topic_id = self.topic_id;
+        let author_user_id = self.author_user_id;
+        let user_id = auth.id;
+        let post = db
+            .transaction(move |tx| {
+                if deleted_at.is_none() {
+                    LogRecord::new(Some(user_id), ModerationAction::RestorePost, author_user_id)
+                        .topic(topic_id)
+                        .post(id)
+                        .insert(tx)?;
+                } else if user_id != author_user_id {
+                    LogRecord::new(Some(user_id), ModerationAction::DeletePost, author_user_id)
+                        .topic(topic_id)
+                        .post(id)
+                        .insert(tx)?;
+                }
+                tx.query_row(
+                    r#"UPDATE posts SET deleted_at = ? WHERE id = ? RETURNING *"#,
+                    params![deleted_at, id],
+                    Post::try_from_row,
+                )
+            })
+            .await??;
+        Ok(post)
     }
     /// Checks visibility of post, but not the topic it belongs to
-    fn is_visible_to(&self, session: &ReadableSession) -> bool {
+    pub fn is_visible_to(&self, auth: Option<&UserAuth>) -> bool {
+        let privileged = auth.map(|a| a.is_admin() || a.is_moderator()) == Some(true);
         if self.deleted_at.is_some() {
             // Deleted topic is only visible to admin and moderator
-            session.is_admin() || session.is_moderator()
+            privileged
         } else if !self.public {
             // Hidden post is only visible to admin, moderator, and topic author
-            session.is_admin()
-                || session.is_moderator()
-                || session.user_id() == Some(self.author_user_id)
+            privileged || auth.map(|a| a.id) == Some(self.author_user_id)
         } else {
             // Public post is visible to everyone
             true
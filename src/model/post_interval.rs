@@ -0,0 +1,134 @@
+use chrono::{DateTime, Duration, Utc};
+use rusqlite::{params, Connection};
+
+use crate::auth::user_role::UserRole;
+use crate::configuration::PostingSettings;
+
+/// Minimum time between two topics, posts or replies of a user with `role`
+pub fn interval(settings: PostingSettings, role: UserRole) -> Duration {
+    let secs = match role {
+        UserRole::Admin => settings.admin_interval_secs,
+        UserRole::Moderator => settings.moderator_interval_secs,
+        _ => settings.author_interval_secs,
+    };
+    Duration::seconds(secs as i64)
+}
+
+/// Records that the user posts now, unless their last topic, post or reply
+/// is less than `interval` old. Returns when they may post again in that case.
+/// Run it in the same transaction as the insert, opened as
+/// `TransactionBehavior::Immediate` so that concurrent posts wait for each
+/// other instead of failing with `SQLITE_BUSY`.
+pub fn claim(
+    conn: &Connection,
+    user_id: i64,
+    interval: Duration,
+) -> Result<Option<DateTime<Utc>>, rusqlite::Error> {
+    let now = Utc::now();
+    let last_post_at: Option<DateTime<Utc>> = conn.query_row(
+        r#"SELECT last_post_at FROM users WHERE id = ?"#,
+        [user_id],
+        |row| row.get(0),
+    )?;
+    if let Some(retry_at) = last_post_at.map(|last| last + interval) {
+        if now < retry_at {
+            return Ok(Some(retry_at));
+        }
+    }
+    conn.execute(
+        r#"UPDATE users SET last_post_at = ? WHERE id = ?"#,
+        params![now, user_id],
+    )?;
+    Ok(None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sql::migrations;
+
+    fn connect() -> Connection {
+        let mut conn = Connection::open_in_memory().unwrap();
+        migrations().to_latest(&mut conn).unwrap();
+        conn.execute(
+            r#"INSERT INTO users(id, username, phc) VALUES (2, 'alice', 'x')"#,
+            [],
+        )
+        .unwrap();
+        conn
+    }
+
+    fn settings() -> PostingSettings {
+        PostingSettings {
+            author_interval_secs: 30,
+            moderator_interval_secs: 10,
+            admin_interval_secs: 0,
+        }
+    }
+
+    #[test]
+    fn second_post_within_the_interval_is_rejected() {
+        let conn = connect();
+        let interval = Duration::seconds(30);
+        assert_eq!(claim(&conn, 2, interval).unwrap(), None);
+        let retry_at = claim(&conn, 2, interval).unwrap().unwrap();
+        let wait = retry_at - Utc::now();
+        assert!(wait > Duration::seconds(28) && wait <= interval, "{}", wait);
+    }
+
+    #[test]
+    fn post_after_the_interval_is_accepted() {
+        let conn = connect();
+        let interval = Duration::seconds(30);
+        let last_post_at = Utc::now() - Duration::seconds(31);
+        conn.execute(
+            r#"UPDATE users SET last_post_at = ? WHERE id = 2"#,
+            [last_post_at],
+        )
+        .unwrap();
+        assert_eq!(claim(&conn, 2, interval).unwrap(), None);
+        let claimed_at: DateTime<Utc> = conn
+            .query_row(
+                r#"SELECT last_post_at FROM users WHERE id = 2"#,
+                [],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert!(claimed_at > last_post_at);
+    }
+
+    #[test]
+    fn rejected_post_does_not_extend_the_interval() {
+        let conn = connect();
+        let interval = Duration::seconds(30);
+        assert_eq!(claim(&conn, 2, interval).unwrap(), None);
+        let retry_at = claim(&conn, 2, interval).unwrap();
+        assert_eq!(claim(&conn, 2, interval).unwrap(), retry_at);
+    }
+
+    #[test]
+    fn each_role_gets_its_own_interval() {
+        let settings = settings();
+        for (role, secs) in [
+            (UserRole::Author, 30),
+            (UserRole::Moderator, 10),
+            (UserRole::Admin, 0),
+        ] {
+            assert_eq!(
+                interval(settings, role),
+                Duration::seconds(secs),
+                "{:?}",
+                role
+            );
+        }
+    }
+
+    #[test]
+    fn zero_interval_never_rejects() {
+        let conn = connect();
+        let interval = interval(settings(), UserRole::Admin);
+        for _ in 0..3 {
+            assert_eq!(claim(&conn, 2, interval).unwrap(), None);
+        }
+    }
+}
@@ -0,0 +1,315 @@
+use std::collections::HashMap;
+
+use axum::response::{IntoResponse, Response};
+use chrono::{DateTime, Utc};
+use hyper::StatusCode;
+use itertools::Itertools;
+use rusqlite::{params, TransactionBehavior};
+use thiserror::*;
+
+use crate::auth::extractor::UserAuth;
+use crate::configuration::PostingSettings;
+use crate::model::{
+    database::{Database, InteractError},
+    from_row::FromRow,
+    moderation_log::{LogRecord, ModerationAction},
+    post::Post,
+    post_interval,
+    topic::Topic,
+};
+
+/// A short comment attached to a post. Replies are not numbered, and are
+/// deleted for real instead of being soft-deleted.
+#[derive(Debug)]
+pub struct Reply {
+    pub id: i64,
+    pub post_id: i64,
+    pub author_user_id: i64,
+    pub body: String,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Error, Debug)]
+pub enum ReplyError {
+    #[error("reply `{0}` not found")]
+    NotFound(i64),
+    #[error("forbidden: {0}")]
+    Forbidden(String),
+    #[error(transparent)]
+    RusqliteError(#[from] rusqlite::Error),
+    #[error(transparent)]
+    InteractError(#[from] InteractError),
+    #[error("replying again too soon, retry at {0}")]
+    TooSoon(DateTime<Utc>),
+}
+
+impl IntoResponse for ReplyError {
+    fn into_response(self) -> Response {
+        match self {
+            ReplyError::NotFound(_) => (StatusCode::NOT_FOUND, "404 not found"),
+            ReplyError::Forbidden(_) => (StatusCode::FORBIDDEN, "403 forbidden"),
+            ReplyError::TooSoon(_) => (StatusCode::TOO_MANY_REQUESTS, "429 too many requests"),
+            ReplyError::RusqliteError(_) => (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "500 Internal Server Error",
+            ),
+            ReplyError::InteractError(_) => (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "500 Internal Server Error",
+            ),
+        }
+        .into_response()
+    }
+}
+
+type Result<T, E = ReplyError> = std::result::Result<T, E>;
+
+impl Reply {
+    /// Queries a reply regardless of who may see it. Callers check the
+    /// visibility of its post and topic.
+    pub async fn query(db: &Database, id: i64) -> Result<Reply> {
+        db.interact(move |conn| {
+            conn.query_row(
+                r#"SELECT * FROM replies WHERE id = ?"#,
+                [id],
+                Reply::try_from_row,
+            )
+        })
+        .await?
+        .map_err(|e| match e {
+            rusqlite::Error::QueryReturnedNoRows => ReplyError::NotFound(id),
+            e => e.into(),
+        })
+    }
+
+    /// Queries the replies of several posts at once, oldest first, keyed by
+    /// post ID. Replies are visible exactly when their post is, so posts
+    /// hidden from the viewer are skipped. Does not check the topic itself.
+    pub async fn query_by_posts(
+        db: &Database,
+        auth: Option<&UserAuth>,
+        posts: &[Post],
+    ) -> Result<HashMap<i64, Vec<Reply>>> {
+        let post_ids = posts
+            .iter()
+            .filter(|post| post.is_visible_to(auth))
+            .map(|post| post.id)
+            .collect_vec();
+        if post_ids.is_empty() {
+            return Ok(HashMap::new());
+        }
+        let replies = db
+            .interact(move |conn| -> Result<Vec<Reply>, rusqlite::Error> {
+                let placeholders = post_ids.iter().map(|_| "?").join(",");
+                let mut stmt = conn.prepare(&format!(
+                    r#"SELECT * FROM replies WHERE post_id IN ({}) ORDER BY id"#,
+                    placeholders
+                ))?;
+                let replies =
+                    stmt.query_map(rusqlite::params_from_iter(post_ids), Reply::try_from_row)?;
+                replies.collect()
+            })
+            .await??;
+        Ok(replies.into_iter().into_group_map_by(|r| r.post_id))
+    }
+
+    /// Replies to `post` in `topic`, both of which the caller has already
+    /// checked are visible to `auth`
+    pub async fn insert(
+        db: &Database,
+        auth: &UserAuth,
+        settings: PostingSettings,
+        topic: &Topic,
+        post: &Post,
+        body: &str,
+    ) -> Result<Reply> {
+        if !auth.can_post() {
+            return Err(ReplyError::Forbidden(format!(
+                "user {} ({:?}) cannot reply",
+                auth.id, auth.role
+            )));
+        }
+        if topic.deleted_at.is_some() || post.deleted_at.is_some() {
+            return Err(ReplyError::Forbidden(format!(
+                "post {} is deleted",
+                post.id
+            )));
+        }
+        let user_id = auth.id;
+        let interval = post_interval::interval(settings, auth.role);
+        let post_id = post.id;
+        let body = body.to_owned();
+        let reply = db
+            .transaction_with_behavior(TransactionBehavior::Immediate, move |tx| {
+                if let Some(retry_at) = post_interval::claim(tx, user_id, interval)? {
+                    return Err(ReplyError::TooSoon(retry_at));
+                }
+                Ok(tx.query_row(
+                    r#"
+                    INSERT INTO replies(post_id, author_user_id, body)
+                    VALUES (?, ?, ?)
+                    RETURNING *
+                    "#,
+                    params![post_id, user_id, body],
+                    Reply::try_from_row,
+                )?)
+            })
+            .await??;
+        Ok(reply)
+    }
+
+    /// Permanently deletes the reply. Only its author, moderators and the
+    /// admin may delete it, as long as they may post.
+    pub async fn delete(self, db: &Database, auth: &UserAuth) -> Result<()> {
+        if !auth.can_edit(self.author_user_id) {
+            return Err(ReplyError::Forbidden(format!(
+                "user {} ({:?}) cannot delete reply {}",
+                auth.id, auth.role, self.id
+            )));
+        }
+        let id = self.id;
+        let post_id = self.post_id;
+        let author_user_id = self.author_user_id;
+        let user_id = auth.id;
+        db.transaction(move |tx| {
+            if user_id != author_user_id {
+                LogRecord::new(Some(user_id), ModerationAction::DeleteReply, author_user_id)
+                    .post(post_id)
+                    .reply(id)
+                    .insert(tx)?;
+            }
+            tx.execute(r#"DELETE FROM replies WHERE id = ?"#, [id])
+        })
+        .await??;
+        Ok(())
+    }
+}
+
+impl FromRow for Reply {
+    fn try_from_row(row: &rusqlite::Row) -> Result<Self, rusqlite::Error> {
+        Ok(Self {
+            id: row.get("id")?,
+            post_id: row.get("post_id")?,
+            author_user_id: row.get("author_user_id")?,
+            body: row.get("body")?,
+            created_at: row.get("created_at")?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Duration;
+
+    use super::*;
+    use crate::auth::user_role::UserRole;
+    use crate::configuration::{PragmaSettings, SQLite3Settings};
+    use crate::sql::migrations;
+
+    /// Alice (UID 2) replied to the admin's post
+    async fn database() -> (Database, Reply) {
+        let db = Database::new(&SQLite3Settings {
+            connection: ":memory:".to_owned(),
+            pool_size: 1,
+            pragmas: PragmaSettings::default(),
+        })
+        .unwrap();
+        let reply = db
+            .interact(|conn| {
+                migrations().to_latest(conn).unwrap();
+                conn.execute_batch(
+                    r#"
+                    INSERT INTO users(id, username, phc) VALUES (2, 'alice', 'x');
+                    INSERT INTO topics(id, author_user_id, title) VALUES (1, 1, 'Topic');
+                    INSERT INTO posts(id, topic_id, author_user_id, body, post_number)
+                    VALUES (1, 1, 1, 'Post', 0);
+                    "#,
+                )
+                .unwrap();
+                conn.query_row(
+                    r#"INSERT INTO replies(post_id, author_user_id, body) VALUES (1, 2, 'Reply') RETURNING *"#,
+                    [],
+                    Reply::try_from_row,
+                )
+                .unwrap()
+            })
+            .await
+            .unwrap();
+        (db, reply)
+    }
+
+    async fn auth(db: &Database, user_id: i64) -> UserAuth {
+        let role = db
+            .interact(move |conn| UserRole::from_db(conn, user_id))
+            .await
+            .unwrap()
+            .unwrap();
+        UserAuth { id: user_id, role }
+    }
+
+    async fn replies(db: &Database) -> i64 {
+        db.interact(|conn| conn.query_row(r#"SELECT count(*) FROM replies"#, [], |row| row.get(0)))
+            .await
+            .unwrap()
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn author_deletes_own_reply() {
+        let (db, reply) = database().await;
+        let auth = auth(&db, 2).await;
+        reply.delete(&db, &auth).await.unwrap();
+        assert_eq!(replies(&db).await, 0);
+    }
+
+    #[tokio::test]
+    async fn muted_author_cannot_delete_own_reply() {
+        let (db, reply) = database().await;
+        let muted_until = Utc::now() + Duration::hours(1);
+        db.interact(move |conn| {
+            conn.execute(
+                r#"UPDATE users SET muted_until = ? WHERE id = 2"#,
+                [muted_until],
+            )
+        })
+        .await
+        .unwrap()
+        .unwrap();
+        let auth = auth(&db, 2).await;
+        assert_eq!(auth.role, UserRole::Viewer);
+        assert!(matches!(
+            reply.delete(&db, &auth).await,
+            Err(ReplyError::Forbidden(_))
+        ));
+        assert_eq!(replies(&db).await, 1);
+    }
+
+    #[tokio::test]
+    async fn banned_author_cannot_delete_own_reply() {
+        let (db, reply) = database().await;
+        db.interact(|conn| {
+            conn.execute(
+                r#"UPDATE users SET banned_at = CURRENT_TIMESTAMP WHERE id = 2"#,
+                [],
+            )
+        })
+        .await
+        .unwrap()
+        .unwrap();
+        let auth = auth(&db, 2).await;
+        assert_eq!(auth.role, UserRole::Banned);
+        assert!(matches!(
+            reply.delete(&db, &auth).await,
+            Err(ReplyError::Forbidden(_))
+        ));
+        assert_eq!(replies(&db).await, 1);
+    }
+
+    #[tokio::test]
+    async fn admin_deletes_any_reply() {
+        let (db, reply) = database().await;
+        let auth = auth(&db, 1).await;
+        reply.delete(&db, &auth).await.unwrap();
+        assert_eq!(replies(&db).await, 0);
+    }
+}
@@ -0,0 +1,51 @@
+use chrono::{DateTime, Utc};
+use rusqlite::Connection;
+
+use super::from_row::FromRow;
+
+/// A topic title or post body as it was before an edit replaced it
+#[derive(Debug)]
+pub struct Revision {
+    pub id: i64,
+    pub topic_id: Option<i64>,
+    pub post_id: Option<i64>,
+    pub content: String,
+    /// User who replaced this version
+    pub edited_by: i64,
+    /// When this version was replaced
+    pub created_at: DateTime<Utc>,
+}
+
+impl Revision {
+    /// Past titles of a topic, oldest first
+    pub fn query_by_topic_id(
+        conn: &Connection,
+        topic_id: i64,
+    ) -> Result<Vec<Revision>, rusqlite::Error> {
+        let mut stmt = conn.prepare(r#"SELECT * FROM revisions WHERE topic_id = ? ORDER BY id"#)?;
+        let revisions = stmt.query_map([topic_id], Revision::try_from_row)?;
+        revisions.collect()
+    }
+    /// Past bodies of a post, oldest first
+    pub fn query_by_post_id(
+        conn: &Connection,
+        post_id: i64,
+    ) -> Result<Vec<Revision>, rusqlite::Error> {
+        let mut stmt = conn.prepare(r#"SELECT * FROM revisions WHERE post_id = ? ORDER BY id"#)?;
+        let revisions = stmt.query_map([post_id], Revision::try_from_row)?;
+        revisions.collect()
+    }
+}
+
+impl FromRow for Revision {
+    fn try_from_row(row: &rusqlite::Row) -> Result<Self, rusqlite::Error> {
+        Ok(Self {
+            id: row.get("id")?,
+            topic_id: row.get("topic_id")?,
+            post_id: row.get("post_id")?,
+            content: row.get("content")?,
+            edited_by: row.get("edited_by")?,
+            created_at: row.get("created_at")?,
+        })
+    }
+}
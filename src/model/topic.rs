@@ -1,13 +1,25 @@
 use axum::response::{IntoResponse, Response};
-use axum_sessions::extractors::ReadableSession;
 use chrono::{DateTime, Utc};
 use hyper::StatusCode;
 // use eyre::*;
 use thiserror::*;
 
-use rusqlite::{params, Connection, OptionalExtension};
+use rusqlite::{params, OptionalExtension, TransactionBehavior};
+use serde::{Deserialize, Serialize};
+use validator::ValidationErrors;
 
-use super::{from_row::FromRow, post::Post};
+use super::{
+    database::{Database, InteractError},
+    from_row::FromRow,
+    moderation::ModerationError,
+    moderation_log::{LogRecord, ModerationAction},
+    post::{Post, PostError},
+    post_interval,
+    reply::ReplyError,
+    user::User,
+};
+use crate::auth::extractor::UserAuth;
+use crate::configuration::PostingSettings;
 
 #[derive(Error, Debug)]
 pub enum TopicError {
@@ -16,7 +28,17 @@ pub enum TopicError {
     #[error("forbidden: {0}")]
     Forbidden(String),
     #[error(transparent)]
+    Invalid(#[from] ValidationErrors),
+    #[error(transparent)]
     RusqliteError(#[from] rusqlite::Error),
+    #[error(transparent)]
+    InteractError(#[from] InteractError),
+    #[error(transparent)]
+    PostError(#[from] PostError),
+    #[error(transparent)]
+    ReplyError(#[from] ReplyError),
+    #[error(transparent)]
+    ModerationError(#[from] ModerationError),
     #[error("Internal error: {0}")]
     InternalError(String),
     #[error(transparent)]
@@ -28,14 +50,18 @@ impl IntoResponse for TopicError {
         match self {
             TopicError::NotFound(_) => (StatusCode::NOT_FOUND, "404 not found"),
             TopicError::Forbidden(_) => (StatusCode::FORBIDDEN, "403 forbidden"),
-            TopicError::DeadpoolError => (
+            TopicError::Invalid(_) => (StatusCode::BAD_REQUEST, "400 bad request"),
+            TopicError::RusqliteError(_) => (
                 StatusCode::INTERNAL_SERVER_ERROR,
                 "500 Internal Server Error",
             ),
-            TopicError::RusqliteError(_) => (
+            TopicError::InteractError(_) => (
                 StatusCode::INTERNAL_SERVER_ERROR,
                 "500 Internal Server Error",
             ),
+            TopicError::PostError(e) => return e.into_response(),
+            TopicError::ReplyError(e) => return e.into_response(),
+            TopicError::ModerationError(e) => return e.into_response(),
             TopicError::InternalError(_) => (
                 StatusCode::INTERNAL_SERVER_ERROR,
                 "500 Internal Server Error",
@@ -51,12 +77,23 @@ impl IntoResponse for TopicError {
 
 type Result<T, E = TopicError> = std::result::Result<T, E>;
 
+/// Describes the viewer in error messages
+fn cred_str(auth: Option<&UserAuth>) -> String {
+    match auth {
+        Some(auth) => format!("user {} ({:?})", auth.id, auth.role),
+        None => "anonymous".to_owned(),
+    }
+}
+
 #[derive(Debug)]
 pub struct Topic {
     pub id: i64,
     pub author_user_id: i64,
     pub title: String,
+    /// Posts that are not deleted
     pub number_posts: i64,
+    /// Number of the next post, one past the last post including deleted ones
+    pub next_post_number: i64,
     pub public: bool,
     pub created_at: DateTime<Utc>,
     pub updated_at: Option<DateTime<Utc>>,
@@ -68,33 +105,36 @@ pub struct Topic {
 impl Topic {
     /// Queries a topic for a certain role
     pub async fn query(
-        conn: &Connection,
-        session: &ReadableSession,
+        db: &Database,
+        auth: Option<&UserAuth>,
         id: i64,
     ) -> Result<Topic, TopicError> {
-        let topic = conn
-            .query_row(
-                r#"SELECT * FROM topics WHERE id = ?"#,
-                [id],
-                Topic::try_from_row,
-            )
+        let topic = db
+            .interact(move |conn| {
+                conn.query_row(
+                    r#"SELECT * FROM topics WHERE id = ?"#,
+                    [id],
+                    Topic::try_from_row,
+                )
+            })
+            .await?
             .map_err(|e| match e {
                 rusqlite::Error::QueryReturnedNoRows => TopicError::NotFound(id),
                 e => e.into(),
             })?;
-        if topic.is_visible_to(session) {
+        if topic.is_visible_to(auth) {
             Ok(topic)
         } else {
             Err(TopicError::Forbidden(format!(
                 "{} cannot view topic {}",
-                id,
-                session.cred_str()
+                cred_str(auth),
+                id
             )))
         }
     }
     pub async fn query_visibility(
-        db: &Connection,
-        session: &ReadableSession,
+        db: &Database,
+        auth: Option<&UserAuth>,
         id: i64,
     ) -> Result<bool, TopicError> {
         if let Some((author_user_id, public, deleted_at)) = db
@@ -106,36 +146,34 @@ impl Topic {
                 )
                 .optional()
             })
-            .await
-            .map_err(|_| TopicError::DeadpoolError)??
+            .await??
         {
             Ok(Self::topic_is_visible_to(
                 author_user_id,
                 public,
                 &deleted_at,
-                session,
+                auth,
             ))
         } else {
             Err(TopicError::NotFound(id))
         }
     }
-    fn is_visible_to(&self, session: &ReadableSession) -> bool {
-        Self::topic_is_visible_to(self.author_user_id, self.public, &self.deleted_at, session)
+    fn is_visible_to(&self, auth: Option<&UserAuth>) -> bool {
+        Self::topic_is_visible_to(self.author_user_id, self.public, &self.deleted_at, auth)
     }
     fn topic_is_visible_to(
         author_user_id: i64,
         public: bool,
         deleted_at: &Option<DateTime<Utc>>,
-        session: &ReadableSession,
+        auth: Option<&UserAuth>,
     ) -> bool {
+        let privileged = auth.map(|a| a.is_admin() || a.is_moderator()) == Some(true);
         if deleted_at.is_some() {
             // Deleted topic is only visible to admin and moderator
-            session.is_admin() || session.is_moderator()
+            privileged
         } else if !public {
             // Hidden post is only visible to admin, moderator, and topic author
-            session.is_admin()
-                || session.is_moderator()
-                || session.user_id() == Some(author_user_id)
+            privileged || auth.map(|a| a.id) == Some(author_user_id)
         } else {
             // Public post is visible to everyone
             true
@@ -144,63 +182,279 @@ impl Topic {
 }
 
 impl Topic {
+    /// Creates a topic together with its opening post
     pub async fn insert_topic(
-        db: &Connection,
-        session: &ReadableSession,
+        db: &Database,
+        auth: &UserAuth,
+        settings: PostingSettings,
         title: &str,
         public: bool,
-        body: bool,
+        body: &str,
     ) -> Result<(Self, Post), TopicError> {
-        if !session.can_post() {
+        if !auth.can_post() {
             return Err(TopicError::Forbidden(format!(
                 "`{}` cannot post topic",
-                session.cred_str()
+                cred_str(Some(auth))
             )));
         }
-        let user_id = session.user_id().ok_or(TopicError::InternalError(
-            "Session User ID failed".to_owned(),
-        ))?;
+        let user_id = auth.id;
+        let interval = post_interval::interval(settings, auth.role);
         let title = title.to_owned();
         let body = body.to_owned();
         let (topic, post) = db
-            .interact(move |conn| -> Result<(Topic, Post), rusqlite::Error> {
-                let tx = conn.transaction()?;
-                let topic = tx.query_row(
+            .transaction_with_behavior(
+                TransactionBehavior::Immediate,
+                move |tx| -> Result<(Topic, Post), TopicError> {
+                    if let Some(retry_at) = post_interval::claim(tx, user_id, interval)? {
+                        return Err(PostError::TooSoon(retry_at).into());
+                    }
+                    let topic = tx.query_row(
+                        r#"
+                    INSERT INTO topics(author_user_id, title, public)
+                    VALUES (?, ?, ?)
+                    RETURNING *
+                    "#,
+                        params![user_id, title, public],
+                        Topic::try_from_row,
+                    )?;
+                    let post = tx.query_row(
+                        r#"
+                    INSERT INTO posts(topic_id, author_user_id, body, public, post_number)
+                    VALUES (?1, ?2, ?3, ?4, (SELECT next_post_number FROM topics WHERE id = ?1))
+                    RETURNING *
+                    "#,
+                        params![topic.id, user_id, body, public],
+                        Post::try_from_row,
+                    )?;
+                    Ok((topic, post))
+                },
+            )
+            .await??;
+        Ok((topic, post))
+    }
+
+    /// Changes the title, keeping the previous one as a revision
+    pub async fn edit_title(self, db: &Database, auth: &UserAuth, title: &str) -> Result<Topic> {
+        if self.deleted_at.is_some() || !auth.can_edit(self.author_user_id) {
+            return Err(TopicError::Forbidden(format!(
+                "{} cannot edit topic {}",
+                cred_str(Some(auth)),
+                self.id
+            )));
+        }
+        if self.title == title {
+            return Ok(self);
+        }
+        let id = self.id;
+        let author_user_id = self.author_user_id;
+        let user_id = auth.id;
+        let title = title.to_owned();
+        let topic = db
+            .transaction(move |tx| {
+                tx.execute(
                     r#"
-                INSERT INTO topics(author_user_id, title, public)
-                VALUES (?, ?, ?)
-                RETURNING *
-                "#,
-                    params![user_id, title, public],
-                    Topic::try_from_row,
+                    INSERT INTO revisions(topic_id, content, edited_by)
+                    SELECT id, title, ? FROM topics WHERE id = ?
+                    "#,
+                    params![user_id, id],
                 )?;
-                let post = tx.query_row(
+                if user_id != author_user_id {
+                    LogRecord::new(Some(user_id), ModerationAction::EditTopic, author_user_id)
+                        .topic(id)
+                        .insert(tx)?;
+                }
+                tx.query_row(
                     r#"
-                    INSERT INTO posts(topic_id, author_user_id, body, public)
-                    VALUES (?, ?, ?, ?)
+                    UPDATE topics SET title = ?, last_updated_by = ?
+                    WHERE id = ?
                     RETURNING *
                     "#,
-                    params![topic.id, user_id, body, public],
-                    Post::try_from_row,
-                )?;
-                tx.commit()?;
-                Ok((topic, post))
+                    params![title, user_id, id],
+                    Topic::try_from_row,
+                )
             })
-            .await
-            .map_err(|_| TopicError::DeadpoolError)??;
-        Ok((topic, post))
+            .await??;
+        Ok(topic)
+    }
+
+    /// Soft-deletes the topic. Its author, moderators and the admin may
+    /// delete it.
+    pub async fn delete(self, db: &Database, auth: &UserAuth) -> Result<Topic> {
+        if self.deleted_at.is_some() || !auth.can_edit(self.author_user_id) {
+            return Err(TopicError::Forbidden(format!(
+                "{} cannot delete topic {}",
+                cred_str(Some(auth)),
+                self.id
+            )));
+        }
+        self.set_deleted_at(db, auth, Some(Utc::now())).await
+    }
+
+    /// Undoes a soft-deletion. Only moderators and the admin may restore.
+    pub async fn restore(self, db: &Database, auth: &UserAuth) -> Result<Topic> {
+        if self.deleted_at.is_none() || !(auth.is_moderator() || auth.is_admin()) {
+            return Err(TopicError::Forbidden(format!(
+                "{} cannot restore topic {}",
+                cred_str(Some(auth)),
+                self.id
+            )));
+        }
+        self.set_deleted_at(db, auth, None).await
+    }
+
+    /// Deleting someone else's topic and restoring are logged
+    async fn set_deleted_at(
+        self,
+        db: &Database,
+        auth: &UserAuth,
+        deleted_at: Option<DateTime<Utc>>,
+    ) -> Result<Topic> {
+        let id = self.id;
+        let author_user_id = self.author_user_id;
+        let user_id = auth.id;
+        let topic = db
+            .transaction(move |tx| {
+                if deleted_at.is_none() {
+                    LogRecord::new(
+                        Some(user_id),
+                        ModerationAction::RestoreTopic,
+                        author_user_id,
+                    )
+                    .topic(id)
+                    .insert(tx)?;
+                } else if user_id != author_user_id {
+                    LogRecord::new(Some(user_id), ModerationAction::DeleteTopic, author_user_id)
+                        .topic(id)
+                        .insert(tx)?;
+                }
+                tx.query_row(
+                    r#"UPDATE topics SET deleted_at = ? WHERE id = ? RETURNING *"#,
+                    params![deleted_at, id],
+                    Topic::try_from_row,
+                )
+            })
+            .await??;
+        Ok(topic)
     }
 }
 
 impl Topic {
-    pub async fn author(&self, _db: &Connection, _session: &ReadableSession) {
-        todo!()
+    pub async fn author(&self, db: &Database) -> Result<User, TopicError> {
+        let author_user_id = self.author_user_id;
+        db.interact(move |conn| User::query(conn, author_user_id))
+            .await??
+            .ok_or_else(|| TopicError::InternalError(format!("user {} not found", author_user_id)))
+    }
+    pub async fn last_updated_by(&self, db: &Database) -> Result<Option<User>, TopicError> {
+        if let Some(user_id) = self.last_updated_by {
+            Ok(db
+                .interact(move |conn| User::query(conn, user_id))
+                .await??)
+        } else {
+            Ok(None)
+        }
     }
-    pub async fn last_updated_by(&self, _db: &Connection, _session: &ReadableSession) {
-        todo!()
+    /// Posts on page `page` (starting from 0) visible to the viewer
+    pub async fn posts(
+        &self,
+        db: &Database,
+        auth: Option<&UserAuth>,
+        page: i64,
+        posts_per_page: i64,
+    ) -> Result<Vec<Post>, TopicError> {
+        let start = page * posts_per_page;
+        Ok(Post::query_by_topic_id(db, auth, self.id, start..start + posts_per_page).await?)
     }
-    pub async fn posts(&self, _db: &Connection, _session: &ReadableSession) {
-        todo!()
+}
+
+/// Position in the topic listing, as (last activity in Unix seconds, topic ID)
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct TopicCursor {
+    pub before_at: i64,
+    pub before_id: i64,
+}
+
+/// A topic as shown in the topic listing
+#[derive(Debug)]
+pub struct TopicListing {
+    pub topic: Topic,
+    pub author_username: String,
+    pub last_activity_at: DateTime<Utc>,
+}
+
+pub struct TopicPage {
+    pub topics: Vec<TopicListing>,
+    /// Cursor of the next page, if there is one
+    pub next: Option<TopicCursor>,
+}
+
+impl Topic {
+    /// Lists topics visible to the viewer, most recently active first
+    pub async fn list(
+        db: &Database,
+        auth: Option<&UserAuth>,
+        cursor: Option<TopicCursor>,
+        limit: usize,
+    ) -> Result<TopicPage, TopicError> {
+        let user_id = auth.map(|a| a.id);
+        let privileged = auth.map(|a| a.is_admin() || a.is_moderator()) == Some(true);
+        let (before_at, before_id) = cursor
+            .map(|c| (c.before_at, c.before_id))
+            .unwrap_or((i64::MAX, i64::MAX));
+        let mut topics = db
+            .interact(move |conn| -> Result<Vec<TopicListing>, rusqlite::Error> {
+                let mut stmt = conn.prepare(
+                    r#"
+                    SELECT * FROM (
+                        SELECT
+                            t.*,
+                            u.username author_username,
+                            datetime(COALESCE(t.updated_at, t.created_at)) last_activity_at
+                        FROM
+                            topics t
+                            JOIN users u ON u.id = t.author_user_id
+                        WHERE
+                            :privileged
+                            OR (t.deleted_at IS NULL AND (t.public OR t.author_user_id = :user_id))
+                    )
+                    WHERE
+                        (CAST(strftime('%s', last_activity_at) AS INTEGER), id)
+                            < (:before_at, :before_id)
+                    ORDER BY last_activity_at DESC, id DESC
+                    LIMIT :limit
+                    "#,
+                )?;
+                let rows = stmt.query_map(
+                    rusqlite::named_params! {
+                        ":privileged": privileged,
+                        ":user_id": user_id,
+                        ":before_at": before_at,
+                        ":before_id": before_id,
+                        ":limit": limit as i64 + 1,
+                    },
+                    |row| {
+                        Ok(TopicListing {
+                            topic: Topic::try_from_row(row)?,
+                            author_username: row.get("author_username")?,
+                            last_activity_at: row.get("last_activity_at")?,
+                        })
+                    },
+                )?;
+                rows.collect()
+            })
+            .await??;
+        // Fetched one extra row to know whether there is a next page
+        let next = if topics.len() > limit {
+            topics.truncate(limit);
+            topics.last().map(|t| TopicCursor {
+                before_at: t.last_activity_at.timestamp(),
+                before_id: t.topic.id,
+            })
+        } else {
+            None
+        };
+        topics.retain(|t| t.topic.is_visible_to(auth));
+        Ok(TopicPage { topics, next })
     }
 }
 
@@ -211,6 +465,7 @@ impl FromRow for Topic {
             author_user_id: row.get("author_user_id")?,
             title: row.get("title")?,
             number_posts: row.get("number_posts")?,
+            next_post_number: row.get("next_post_number")?,
             public: row.get("public")?,
             created_at: row.get("created_at")?,
             updated_at: row.get("updated_at")?,
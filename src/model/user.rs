@@ -1,3 +1,63 @@
+use std::collections::HashMap;
+
+use chrono::{DateTime, Utc};
+use itertools::Itertools;
+use rusqlite::{Connection, OptionalExtension};
+
+use super::from_row::FromRow;
+
+/// Public profile of a user
+#[derive(Debug, Clone)]
 pub struct User {
-    id: i64,
+    pub id: i64,
+    pub username: String,
+    pub post_signature: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+impl User {
+    pub fn id_by_username(
+        conn: &Connection,
+        username: &str,
+    ) -> Result<Option<i64>, rusqlite::Error> {
+        conn.query_row(
+            r#"SELECT id FROM users WHERE username = ?"#,
+            [username],
+            |row| row.get(0),
+        )
+        .optional()
+    }
+    pub fn query(conn: &Connection, id: i64) -> Result<Option<User>, rusqlite::Error> {
+        conn.query_row(
+            r#"SELECT * FROM users WHERE id = ?"#,
+            [id],
+            User::try_from_row,
+        )
+        .optional()
+    }
+    /// Queries several users at once, keyed by ID
+    pub fn query_many(
+        conn: &Connection,
+        ids: impl IntoIterator<Item = i64>,
+    ) -> Result<HashMap<i64, User>, rusqlite::Error> {
+        let ids = ids.into_iter().unique().collect_vec();
+        let placeholders = ids.iter().map(|_| "?").join(",");
+        let mut stmt = conn.prepare(&format!(
+            r#"SELECT * FROM users WHERE id IN ({})"#,
+            placeholders
+        ))?;
+        let users = stmt.query_map(rusqlite::params_from_iter(ids), User::try_from_row)?;
+        users.map_ok(|u| (u.id, u)).collect()
+    }
+}
+
+impl FromRow for User {
+    fn try_from_row(row: &rusqlite::Row) -> Result<Self, rusqlite::Error> {
+        Ok(Self {
+            id: row.get("id")?,
+            username: row.get("username")?,
+            post_signature: row.get("post_signature")?,
+            created_at: row.get("created_at")?,
+        })
+    }
 }
@@ -0,0 +1,400 @@
+use axum::response::{IntoResponse, Response};
+use chrono::{DateTime, Utc};
+use hyper::StatusCode;
+use rusqlite::{params, OptionalExtension, Transaction};
+use thiserror::*;
+
+use crate::auth::extractor::UserAuth;
+use crate::configuration::AccountSettings;
+use crate::model::{
+    database::{Database, InteractError},
+    from_row::FromRow,
+    moderation_log::{LogRecord, ModerationAction},
+    moderator::Moderator,
+};
+
+/// A user's pending request to wipe their own account
+#[derive(Debug)]
+pub struct WipeRequest {
+    pub id: i64,
+    pub user_id: i64,
+    /// Set for moderators, whose requests only the admin may approve
+    pub requires_admin: bool,
+    pub created_at: DateTime<Utc>,
+}
+
+/// Result of requesting a wipe
+#[derive(Debug)]
+pub enum WipeOutcome {
+    /// The account was wiped at once
+    Wiped,
+    /// The request waits for approval
+    Pending(WipeRequest),
+}
+
+#[derive(Error, Debug)]
+pub enum WipeError {
+    #[error("wipe request `{0}` not found")]
+    NotFound(i64),
+    #[error("forbidden: {0}")]
+    Forbidden(String),
+    #[error(transparent)]
+    RusqliteError(#[from] rusqlite::Error),
+    #[error(transparent)]
+    InteractError(#[from] InteractError),
+}
+
+impl IntoResponse for WipeError {
+    fn into_response(self) -> Response {
+        match self {
+            WipeError::NotFound(_) => (StatusCode::NOT_FOUND, "404 not found"),
+            WipeError::Forbidden(_) => (StatusCode::FORBIDDEN, "403 forbidden"),
+            WipeError::RusqliteError(_) => (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "500 Internal Server Error",
+            ),
+            WipeError::InteractError(_) => (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "500 Internal Server Error",
+            ),
+        }
+        .into_response()
+    }
+}
+
+type Result<T, E = WipeError> = std::result::Result<T, E>;
+
+impl WipeRequest {
+    /// Requests that the viewer's own account be wiped. The account is wiped
+    /// at once unless the request needs approval.
+    pub async fn request(
+        db: &Database,
+        auth: &UserAuth,
+        settings: AccountSettings,
+    ) -> Result<WipeOutcome> {
+        if auth.is_admin() {
+            return Err(WipeError::Forbidden(
+                "the admin account cannot be wiped".to_owned(),
+            ));
+        }
+        let user_id = auth.id;
+        let requires_admin = auth.is_moderator();
+        if !requires_admin && !settings.wipe_requires_approval {
+            db.transaction(move |tx| wipe(tx, user_id)).await??;
+            return Ok(WipeOutcome::Wiped);
+        }
+        let request = db
+            .interact(move |conn| {
+                conn.query_row(
+                    r#"
+                    INSERT INTO wipe_requests(user_id, requires_admin) VALUES (?, ?)
+                    ON CONFLICT(user_id) DO UPDATE SET requires_admin = excluded.requires_admin
+                    RETURNING *
+                    "#,
+                    params![user_id, requires_admin],
+                    WipeRequest::try_from_row,
+                )
+            })
+            .await??;
+        Ok(WipeOutcome::Pending(request))
+    }
+
+    pub async fn query(db: &Database, id: i64) -> Result<WipeRequest> {
+        db.interact(move |conn| {
+            conn.query_row(
+                r#"SELECT * FROM wipe_requests WHERE id = ?"#,
+                [id],
+                WipeRequest::try_from_row,
+            )
+        })
+        .await?
+        .map_err(|e| match e {
+            rusqlite::Error::QueryReturnedNoRows => WipeError::NotFound(id),
+            e => e.into(),
+        })
+    }
+
+    pub async fn query_by_user_id(db: &Database, user_id: i64) -> Result<Option<WipeRequest>> {
+        Ok(db
+            .interact(move |conn| {
+                conn.query_row(
+                    r#"SELECT * FROM wipe_requests WHERE user_id = ?"#,
+                    [user_id],
+                    WipeRequest::try_from_row,
+                )
+                .optional()
+            })
+            .await??)
+    }
+
+    /// Requests the viewer may approve, oldest first, with the usernames of
+    /// the requesting users
+    pub async fn pending(db: &Database, auth: &UserAuth) -> Result<Vec<(WipeRequest, String)>> {
+        if !(auth.is_moderator() || auth.is_admin()) {
+            return Err(WipeError::Forbidden(format!(
+                "user {} cannot review wipe requests",
+                auth.id
+            )));
+        }
+        let admin = auth.is_admin();
+        let user_id = auth.id;
+        Ok(db
+            .interact(move |conn| -> Result<Vec<_>, rusqlite::Error> {
+                let mut stmt = conn.prepare(
+                    r#"
+                    SELECT w.*, u.username
+                    FROM wipe_requests w JOIN users u ON u.id = w.user_id
+                    WHERE :admin OR (NOT w.requires_admin AND w.user_id <> :user_id)
+                    ORDER BY w.id
+                    "#,
+                )?;
+                let rows = stmt.query_map(
+                    rusqlite::named_params! { ":admin": admin, ":user_id": user_id },
+                    |row| Ok((WipeRequest::try_from_row(row)?, row.get("username")?)),
+                )?;
+                rows.collect()
+            })
+            .await??)
+    }
+
+    fn can_approve(&self, auth: &UserAuth) -> bool {
+        auth.is_admin() || (auth.is_moderator() && !self.requires_admin && auth.id != self.user_id)
+    }
+
+    /// Wipes the requesting account
+    pub async fn approve(self, db: &Database, auth: &UserAuth) -> Result<()> {
+        if !self.can_approve(auth) {
+            return Err(WipeError::Forbidden(format!(
+                "user {} cannot approve wipe request {}",
+                auth.id, self.id
+            )));
+        }
+        let user_id = self.user_id;
+        let moderator_id = auth.id;
+        db.transaction(move |tx| {
+            LogRecord::new(Some(moderator_id), ModerationAction::WipeAccount, user_id)
+                .insert(tx)?;
+            wipe(tx, user_id)
+        })
+        .await??;
+        Ok(())
+    }
+
+    /// Drops the request. Reviewers may reject it and its user may withdraw it.
+    pub async fn reject(self, db: &Database, auth: &UserAuth) -> Result<()> {
+        if !(self.can_approve(auth) || auth.id == self.user_id) {
+            return Err(WipeError::Forbidden(format!(
+                "user {} cannot reject wipe request {}",
+                auth.id, self.id
+            )));
+        }
+        let id = self.id;
+        let user_id = self.user_id;
+        let moderator_id = auth.id;
+        db.transaction(move |tx| {
+            if moderator_id != user_id {
+                LogRecord::new(Some(moderator_id), ModerationAction::RejectWipe, user_id)
+                    .insert(tx)?;
+            }
+            tx.execute(r#"DELETE FROM wipe_requests WHERE id = ?"#, [id])
+        })
+        .await??;
+        Ok(())
+    }
+}
+
+/// Deletes every topic, post and reply of the user, and anonymizes the
+/// account so it can no longer log in. The user row itself is kept, so that
+/// revisions and `last_updated_by` of content they edited as a moderator still
+/// name their UID. A moderator is moved to `past_moderators`.
+///
+/// Content depending on the user's topics and posts, including that of other
+/// users, is deleted explicitly, as `ON DELETE CASCADE` does nothing when the
+/// `foreign_keys` pragma is off.
+pub fn wipe(tx: &Transaction, user_id: i64) -> Result<(), rusqlite::Error> {
+    // Topics of other users lose posts, so their counts are recomputed below
+    let topic_ids = {
+        let mut stmt = tx.prepare(
+            r#"
+            SELECT DISTINCT p.topic_id
+            FROM posts p JOIN topics t ON t.id = p.topic_id
+            WHERE p.author_user_id = ?1 AND t.author_user_id <> ?1
+            "#,
+        )?;
+        let ids = stmt.query_map([user_id], |row| row.get::<_, i64>(0))?;
+        ids.collect::<Result<Vec<_>, _>>()?
+    };
+    tx.execute(
+        r#"
+        DELETE FROM replies
+        WHERE author_user_id = ?1 OR post_id IN (
+            SELECT id FROM posts
+            WHERE author_user_id = ?1
+                OR topic_id IN (SELECT id FROM topics WHERE author_user_id = ?1)
+        )
+        "#,
+        [user_id],
+    )?;
+    tx.execute(
+        r#"
+        DELETE FROM revisions
+        WHERE topic_id IN (SELECT id FROM topics WHERE author_user_id = ?1)
+            OR post_id IN (
+                SELECT id FROM posts
+                WHERE author_user_id = ?1
+                    OR topic_id IN (SELECT id FROM topics WHERE author_user_id = ?1)
+            )
+        "#,
+        [user_id],
+    )?;
+    tx.execute(
+        r#"
+        DELETE FROM posts
+        WHERE author_user_id = ?1
+            OR topic_id IN (SELECT id FROM topics WHERE author_user_id = ?1)
+        "#,
+        [user_id],
+    )?;
+    tx.execute(r#"DELETE FROM topics WHERE author_user_id = ?"#, [user_id])?;
+    for topic_id in topic_ids {
+        tx.execute(
+            r#"
+            UPDATE topics SET number_posts = (
+                SELECT COUNT(*) FROM posts WHERE topic_id = ?1 AND deleted_at IS NULL
+            )
+            WHERE id = ?1
+            "#,
+            [topic_id],
+        )?;
+    }
+    Moderator::remove(tx, user_id, "Account wiped")?;
+    tx.execute(
+        r#"DELETE FROM user_sessions WHERE session_user_id = ?"#,
+        [user_id],
+    )?;
+    tx.execute(r#"DELETE FROM wipe_requests WHERE user_id = ?"#, [user_id])?;
+    tx.execute(
+        r#"DELETE FROM password_resets WHERE user_id = ?"#,
+        [user_id],
+    )?;
+    tx.execute(
+        r#"
+        UPDATE users SET
+            username = '[wiped #' || id || ']',
+            phc = NULL,
+            post_signature = NULL,
+            wiped_at = CURRENT_TIMESTAMP
+        WHERE id = ?
+        "#,
+        [user_id],
+    )?;
+    Ok(())
+}
+
+impl FromRow for WipeRequest {
+    fn try_from_row(row: &rusqlite::Row) -> Result<Self, rusqlite::Error> {
+        Ok(Self {
+            id: row.get("id")?,
+            user_id: row.get("user_id")?,
+            requires_admin: row.get("requires_admin")?,
+            created_at: row.get("created_at")?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rusqlite::Connection;
+
+    use super::*;
+    use crate::sql::migrations;
+
+    /// Alice (UID 2), a moderator, has topic 1, where Bob (UID 3) posted and
+    /// replied. Bob has topic 2, where Alice posted and replied, and Bob
+    /// replied to Alice.
+    fn connect(foreign_keys: bool) -> Connection {
+        let mut conn = Connection::open_in_memory().unwrap();
+        migrations().to_latest(&mut conn).unwrap();
+        conn.pragma_update(None, "foreign_keys", foreign_keys)
+            .unwrap();
+        conn.execute_batch(
+            r#"
+            INSERT INTO users(id, username, phc) VALUES (2, 'alice', 'x'), (3, 'bob', 'x');
+            INSERT INTO moderators(moderator_user_id) VALUES (2);
+            INSERT INTO topics(id, author_user_id, title) VALUES (1, 2, 'Alice'), (2, 3, 'Bob');
+            INSERT INTO posts(id, topic_id, author_user_id, body, post_number) VALUES
+                (1, 1, 2, 'first', 0), (2, 1, 3, 'second', 1),
+                (3, 2, 3, 'first', 0), (4, 2, 2, 'second', 1);
+            INSERT INTO replies(id, post_id, author_user_id, body) VALUES
+                (1, 2, 3, 'on topic 1'), (2, 3, 2, 'by Alice'), (3, 4, 3, 'on Alice');
+            INSERT INTO revisions(post_id, content, edited_by) VALUES (2, 'old', 3), (4, 'old', 2);
+            INSERT INTO revisions(topic_id, content, edited_by) VALUES (1, 'old', 2);
+            "#,
+        )
+        .unwrap();
+        LogRecord::new(Some(1), ModerationAction::WipeAccount, 2)
+            .insert(&conn)
+            .unwrap();
+        conn
+    }
+
+    fn ids(conn: &Connection, sql: &str) -> Vec<i64> {
+        let mut stmt = conn.prepare(sql).unwrap();
+        let ids = stmt.query_map([], |row| row.get(0)).unwrap();
+        ids.collect::<Result<_, _>>().unwrap()
+    }
+
+    #[test]
+    fn wipe_removes_content_depending_on_the_user() {
+        for foreign_keys in [true, false] {
+            let mut conn = connect(foreign_keys);
+            let tx = conn.transaction().unwrap();
+            wipe(&tx, 2).unwrap();
+            tx.commit().unwrap();
+
+            assert_eq!(ids(&conn, r#"SELECT id FROM topics"#), [2]);
+            assert_eq!(ids(&conn, r#"SELECT id FROM posts"#), [3]);
+            assert_eq!(ids(&conn, r#"SELECT id FROM replies"#), Vec::<i64>::new());
+            assert_eq!(
+                ids(&conn, r#"SELECT COUNT(*) FROM revisions"#),
+                [0],
+                "foreign_keys={}",
+                foreign_keys
+            );
+            assert_eq!(
+                ids(&conn, r#"SELECT number_posts FROM topics WHERE id = 2"#),
+                [1]
+            );
+        }
+    }
+
+    #[test]
+    fn wipe_records_past_moderator_and_keeps_the_log() {
+        let mut conn = connect(true);
+        let tx = conn.transaction().unwrap();
+        wipe(&tx, 2).unwrap();
+        tx.commit().unwrap();
+
+        assert_eq!(
+            ids(&conn, r#"SELECT moderator_user_id FROM past_moderators"#),
+            [2]
+        );
+        assert_eq!(
+            ids(&conn, r#"SELECT moderator_user_id FROM moderators"#),
+            Vec::<i64>::new()
+        );
+        assert_eq!(
+            ids(&conn, r#"SELECT target_user_id FROM moderation_log"#),
+            [2]
+        );
+        let (username, phc): (String, Option<String>) = conn
+            .query_row(
+                r#"SELECT username, phc FROM users WHERE id = 2"#,
+                [],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .unwrap();
+        assert_eq!(username, "[wiped #2]");
+        assert_eq!(phc, None);
+    }
+}
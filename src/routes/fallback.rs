@@ -1,5 +1,4 @@
-use axum::response::Html;
-use axum::{http::StatusCode, response::IntoResponse};
+use axum::http::StatusCode;
 
 pub async fn handler_404() -> (StatusCode, &'static str) {
     (StatusCode::NOT_FOUND, "404 not found")
@@ -1,28 +1,106 @@
-use axum::{http::StatusCode, response::Html, Extension};
+use axum::{extract::Query, response::Html, Extension};
 use maud::html;
 
+use crate::auth::csrf::CsrfToken;
 use crate::auth::extractor::UserAuth;
+use crate::model::{
+    database::Database,
+    topic::{Topic, TopicCursor, TopicError},
+};
 use tracing::instrument;
 
+use super::moderation::status_notice;
+
+const TOPICS_PER_PAGE: usize = 25;
+
 #[instrument(skip_all)]
-pub async fn handler(auth: Option<UserAuth>) -> Result<Html<String>, StatusCode> {
-    if let Some(auth) = auth {
-        Ok(Html(
-            html! {
-                h1{"Index of Reforum"}
+pub async fn handler(
+    auth: Option<UserAuth>,
+    csrf: CsrfToken,
+    cursor: Option<Query<TopicCursor>>,
+    Extension(db): Extension<Database>,
+) -> Result<Html<String>, TopicError> {
+    let page = Topic::list(
+        &db,
+        auth.as_ref(),
+        cursor.map(|Query(c)| c),
+        TOPICS_PER_PAGE,
+    )
+    .await?;
+    let notice = status_notice(&db, auth.as_ref()).await?;
+    Ok(Html(
+        html! {
+            h1{"Index of Reforum"}
+            (notice)
+            @if let Some(auth) = &auth {
                 p{"Hello, "(format!("user {:?}", auth))"!"}
-                a href="/logout" { "Logout" }
-            }
-            .0,
-        ))
-    } else {
-        Ok(Html(
-            html! {
-                h1{"Index of Reforum"}
+                form method="post" action="/logout" style="display: inline" {
+                    (csrf)
+                    button type="submit" { "Logout" }
+                }
+                @if auth.can_post() {
+                    " "
+                    a href="/topics/new" { "New topic" }
+                }
+                @if auth.is_moderator() || auth.is_admin() {
+                    " "
+                    a href="/wipe-requests" { "Wipe requests" }
+                }
+                @if auth.is_admin() {
+                    " "
+                    a href="/admin/moderators" { "Moderators" }
+                    " "
+                    a href="/admin/log" { "Moderation log" }
+                    " "
+                    a href="/admin/login-failures" { "Failed logins" }
+                    " "
+                    a href="/admin/password-resets" { "Password reset links" }
+                }
+                " "
+                a href="/account/password" { "Change password" }
+                " "
+                a href="/account/wipe" { "Wipe account" }
+            } @else {
                 p{"Hello, Anonymous!"}
                 a href="/login" { "Login" }
             }
-            .0,
-        ))
-    }
+            table {
+                thead {
+                    tr {
+                        th { "Topic" }
+                        th { "Author" }
+                        th { "Posts" }
+                        th { "Views" }
+                        th { "Last activity" }
+                    }
+                }
+                tbody {
+                    @for listing in &page.topics {
+                        tr {
+                            td {
+                                a href=(format!("/topics/{}", listing.topic.id)) {
+                                    (listing.topic.title)
+                                }
+                                @if listing.topic.deleted_at.is_some() {
+                                    " [deleted]"
+                                } @else if !listing.topic.public {
+                                    " [hidden]"
+                                }
+                            }
+                            td { (listing.author_username) }
+                            td { (listing.topic.number_posts) }
+                            td { (listing.topic.views_from_users) }
+                            td { (listing.last_activity_at.format("%Y-%m-%d %H:%M")) }
+                        }
+                    }
+                }
+            }
+            @if let Some(next) = page.next {
+                a href=(format!("/?before_at={}&before_id={}", next.before_at, next.before_id)) {
+                    "Older topics"
+                }
+            }
+        }
+        .0,
+    ))
 }
@@ -1,24 +1,28 @@
+use std::net::{IpAddr, SocketAddr};
+
+use chrono::{DateTime, Utc};
 use thiserror::*;
 
 use axum::{
-    http::StatusCode,
+    extract::ConnectInfo,
+    http::{HeaderMap, StatusCode},
     response::{Html, IntoResponse, Redirect},
-    Extension, Form,
+    Extension,
 };
-use axum_extra::extract::SignedCookieJar;
-use cookie::{Cookie, SameSite};
-use deadpool_sqlite::Pool;
+use axum_sessions::SessionHandle;
 use maud::html;
 
-use time::{Duration, OffsetDateTime};
 use tracing::instrument;
 
 use crate::{
     auth::{
-        authentication::LoginCredential,
-        session::{Session, SessionError},
+        authentication::{self, LoginCredential},
+        csrf::{self, CsrfForm, CsrfToken},
+        extractor::UserAuth,
+        login_throttle,
     },
-    startup::SessionCookieName,
+    configuration::{LoginSettings, PasswordHashSettings},
+    model::database::{Database, InteractError},
 };
 
 #[derive(Error, Debug)]
@@ -27,10 +31,16 @@ pub enum LoginError {
     AlreadyLoggedIn,
     #[error("unauthorized")]
     Unauthorized,
+    #[error("too many failed attempts, retry after {0}")]
+    Throttled(DateTime<Utc>),
+    #[error(transparent)]
+    AuthenticationError(#[from] authentication::LoginError),
+    #[error(transparent)]
+    SessionError(#[from] serde_json::Error),
     #[error(transparent)]
-    SessionError(#[from] SessionError),
+    RusqliteError(#[from] rusqlite::Error),
     #[error(transparent)]
-    Other(#[from] eyre::Error),
+    InteractError(#[from] InteractError),
 }
 
 impl IntoResponse for LoginError {
@@ -42,8 +52,22 @@ impl IntoResponse for LoginError {
             LoginError::Unauthorized => {
                 (StatusCode::UNAUTHORIZED, "Incorrect username or password").into_response()
             }
-            LoginError::SessionError(err) => err.into_response(),
-            LoginError::Other(_) => (
+            LoginError::Throttled(retry_after) => {
+                let secs = (retry_after - Utc::now()).num_seconds().max(1);
+                (
+                    StatusCode::TOO_MANY_REQUESTS,
+                    [("Retry-After", secs.to_string())],
+                    format!(
+                        "Too many failed login attempts, try again after {} UTC",
+                        retry_after.format("%Y-%m-%d %H:%M:%S")
+                    ),
+                )
+                    .into_response()
+            }
+            LoginError::AuthenticationError(_)
+            | LoginError::SessionError(_)
+            | LoginError::RusqliteError(_)
+            | LoginError::InteractError(_) => (
                 StatusCode::INTERNAL_SERVER_ERROR,
                 "500 Internal Server Error",
             )
@@ -52,37 +76,19 @@ impl IntoResponse for LoginError {
     }
 }
 
-async fn new_session_to_cookie(
-    session: &mut Session,
-    session_name: &str,
-    jar: SignedCookieJar,
-    user_id: i64,
-) -> Result<SignedCookieJar, LoginError> {
-    let expires_at = Some(OffsetDateTime::now_utc() + Duration::weeks(4));
-    let session = session.insert(user_id, &expires_at).await?;
-    let jar = if let Ok(s) = serde_json::to_string(&session) {
-        jar.add(
-            Cookie::build(session_name.to_owned(), s)
-                .same_site(SameSite::Strict)
-                .secure(true)
-                .http_only(true)
-                .finish(),
-        )
-    } else {
-        jar
-    };
-    Ok(jar)
-}
-
 #[instrument(skip_all)]
-pub async fn get_handler(session: Session) -> Result<impl IntoResponse, LoginError> {
-    if session.verify().await? {
+pub async fn get_handler(
+    auth: Option<UserAuth>,
+    csrf: CsrfToken,
+) -> Result<impl IntoResponse, LoginError> {
+    if auth.is_some() {
         return Err(LoginError::AlreadyLoggedIn);
     }
     Ok(Html(
         html! {
             h1{"Login"}
             form method="post" {
+                (csrf)
                 div {
                     label for="username" { "Username" }
                     input type="text" name="username";
@@ -98,24 +104,49 @@ pub async fn get_handler(session: Session) -> Result<impl IntoResponse, LoginErr
     ))
 }
 
+/// Address of the client, from the configured proxy header if any. Only the
+/// last entry of a list such as `X-Forwarded-For` is trusted, as the client
+/// controls the ones before the entry appended by the proxy.
+pub fn client_ip(settings: &LoginSettings, headers: &HeaderMap, peer: SocketAddr) -> IpAddr {
+    settings
+        .client_ip_header
+        .as_ref()
+        .and_then(|header| headers.get(header.as_str()))
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.rsplit(',').next())
+        .and_then(|ip| ip.trim().parse().ok())
+        .unwrap_or_else(|| peer.ip())
+}
+
 #[instrument(skip_all, fields(username=cred.username))]
 pub async fn post_handler(
-    Form(cred): Form<LoginCredential>,
-    jar: SignedCookieJar,
-    mut session: Session,
-    Extension(session_name): Extension<SessionCookieName>,
-    Extension(db): Extension<Pool>,
-) -> Result<(SignedCookieJar, Redirect), LoginError> {
-    let conn = db.get().await.unwrap();
-    let user_id = cred.validate(&conn).await?;
+    Extension(session): Extension<SessionHandle>,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    Extension(db): Extension<Database>,
+    Extension(settings): Extension<LoginSettings>,
+    Extension(hash_settings): Extension<PasswordHashSettings>,
+    CsrfForm(cred): CsrfForm<LoginCredential>,
+) -> Result<Redirect, LoginError> {
+    let ip = client_ip(&settings, &headers, peer).to_string();
+    let username = cred.username.clone();
+    let attempt = db
+        .interact(move |conn| login_throttle::begin_attempt(conn, &settings, &username, &ip))
+        .await??
+        .map_err(LoginError::Throttled)?;
+    let user_id = cred.validate(&db, hash_settings).await?;
     if let Some(user_id) = user_id {
-        // If successfully logged in but previous session is valid, clear the session
-        if session.verify().await? {
-            session.purge().await.ok();
-        }
-        let jar = new_session_to_cookie(&mut session, &session_name.0, jar, user_id).await?;
-        Ok((jar, Redirect::to("/")))
+        db.interact(move |conn| login_throttle::record_success(conn, attempt, user_id))
+            .await??;
+        let mut session = session.write().await;
+        // Never reuse the session ID or CSRF token of a previous (possibly
+        // anonymous) session
+        session.regenerate();
+        csrf::reset(&mut session);
+        session.insert("uid", user_id)?;
+        Ok(Redirect::to("/"))
     } else {
+        // The attempt stays recorded as a failure
         Err(LoginError::Unauthorized)
     }
 }
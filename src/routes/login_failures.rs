@@ -0,0 +1,124 @@
+use axum::{
+    extract::Path,
+    response::{Html, IntoResponse, Redirect},
+    Extension,
+};
+use maud::html;
+use tracing::instrument;
+
+use crate::{
+    auth::{
+        csrf::{CsrfCheck, CsrfToken},
+        extractor::UserAuth,
+        login_throttle::{LockedAccount, LoginFailure},
+    },
+    model::{database::Database, moderator::ModeratorError},
+};
+
+const FAILURES_SHOWN: usize = 100;
+
+fn require_admin(auth: &UserAuth) -> Result<(), ModeratorError> {
+    if auth.is_admin() {
+        Ok(())
+    } else {
+        Err(ModeratorError::Forbidden(format!(
+            "user {} is not the admin",
+            auth.id
+        )))
+    }
+}
+
+/// Locked accounts and recent failed logins
+#[instrument(skip_all)]
+pub async fn get_handler(
+    auth: UserAuth,
+    csrf: CsrfToken,
+    Extension(db): Extension<Database>,
+) -> Result<impl IntoResponse, ModeratorError> {
+    require_admin(&auth)?;
+    let (locked, failures) = db
+        .interact(|conn| -> Result<_, rusqlite::Error> {
+            Ok((
+                LockedAccount::list(conn)?,
+                LoginFailure::recent(conn, FAILURES_SHOWN)?,
+            ))
+        })
+        .await??;
+    Ok(Html(
+        html! {
+            a href="/" { "Index of Reforum" }
+            h1 { "Failed logins" }
+            h2 { "Locked accounts" }
+            @if locked.is_empty() {
+                p { "No account is locked." }
+            } @else {
+                table {
+                    thead {
+                        tr {
+                            th { "User" }
+                            th { "Until" }
+                            th { "Unlock" }
+                        }
+                    }
+                    tbody {
+                        @for account in &locked {
+                            tr {
+                                td { (account.username) " (UID " (account.user_id) ")" }
+                                td { (account.locked_until.format("%Y-%m-%d %H:%M")) }
+                                td {
+                                    form method="post" action=(format!("/admin/login-failures/{}/unlock", account.user_id)) {
+                                        (csrf)
+                                        button type="submit" { "Unlock" }
+                                    }
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+            h2 { "Recent failures" }
+            @if failures.is_empty() {
+                p { "No failed logins." }
+            } @else {
+                table {
+                    thead {
+                        tr {
+                            th { "When" }
+                            th { "Username" }
+                            th { "IP address" }
+                        }
+                    }
+                    tbody {
+                        @for failure in &failures {
+                            tr {
+                                td { (failure.created_at.format("%Y-%m-%d %H:%M:%S")) }
+                                td {
+                                    (failure.username)
+                                    @if let Some(user_id) = failure.user_id {
+                                        " (UID " (user_id) ")"
+                                    }
+                                }
+                                td { (failure.ip) }
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        .0,
+    ))
+}
+
+/// Ends the lockout of an account before it expires
+#[instrument(skip_all, fields(user_id=user_id))]
+pub async fn unlock_handler(
+    Path(user_id): Path<i64>,
+    auth: UserAuth,
+    Extension(db): Extension<Database>,
+    _: CsrfCheck,
+) -> Result<Redirect, ModeratorError> {
+    require_admin(&auth)?;
+    db.interact(move |conn| LockedAccount::unlock(conn, user_id))
+        .await??;
+    Ok(Redirect::to("/admin/login-failures"))
+}
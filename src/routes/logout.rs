@@ -1,25 +1,12 @@
-use axum::{response::Redirect, Extension};
-use axum_extra::extract::SignedCookieJar;
-use cookie::Cookie;
+use axum::response::Redirect;
+use axum::Extension;
+use axum_sessions::SessionHandle;
 use tracing::instrument;
 
-use crate::{
-    auth::session::{Session, SessionError},
-    startup::SessionCookieName,
-};
-
-fn remove_session_from_cookie(session_name: &str, jar: SignedCookieJar) -> SignedCookieJar {
-    jar.remove(Cookie::named(session_name.to_owned()))
-}
+use crate::auth::csrf::CsrfCheck;
 
 #[instrument(skip_all)]
-pub async fn handler(
-    jar: SignedCookieJar,
-    Extension(session_name): Extension<SessionCookieName>,
-    session: Session,
-) -> Result<(SignedCookieJar, Redirect), SessionError> {
-    let jar = remove_session_from_cookie(&session_name.0, jar);
-    session.purge().await?;
-
-    Ok((jar, Redirect::to("/")))
+pub async fn handler(Extension(session): Extension<SessionHandle>, _: CsrfCheck) -> Redirect {
+    session.write().await.destroy();
+    Redirect::to("/")
 }
@@ -1,5 +1,15 @@
 pub mod fallback;
 pub mod index;
-// pub mod login;
-// pub mod logout;
-// pub mod topics;
+pub mod login;
+pub mod login_failures;
+pub mod logout;
+pub mod moderation;
+pub mod moderation_log;
+pub mod moderators;
+pub mod password;
+pub mod posts;
+pub mod register;
+pub mod replies;
+pub mod revisions;
+pub mod topics;
+pub mod wipe;
@@ -0,0 +1,215 @@
+use axum::{
+    extract::Path,
+    response::{Html, IntoResponse, Redirect},
+    Extension,
+};
+use chrono::Duration;
+use maud::{html, Markup};
+use serde::Deserialize;
+use tracing::instrument;
+
+use crate::{
+    auth::{
+        csrf::{CsrfForm, CsrfToken},
+        extractor::UserAuth,
+        user_role::UserRole,
+    },
+    model::{
+        database::Database,
+        moderation::{ModerationError, Sanction, UserStatus},
+    },
+};
+
+/// Longest mute a moderator may give, in hours
+const MAX_MUTE_HOURS: i64 = 24 * 365;
+
+#[derive(Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SanctionAction {
+    Ban,
+    Unban,
+    Mute,
+    Unmute,
+}
+
+#[derive(Deserialize)]
+pub struct SanctionForm {
+    action: SanctionAction,
+    reason: String,
+    /// Length of a mute
+    hours: Option<i64>,
+}
+
+/// Tells a banned or muted viewer about their status. Empty for everyone else.
+pub(super) async fn status_notice(
+    db: &Database,
+    auth: Option<&UserAuth>,
+) -> Result<Markup, ModerationError> {
+    let Some(auth) = auth.filter(|a| matches!(a.role, UserRole::Banned | UserRole::Viewer)) else {
+        return Ok(html! {});
+    };
+    let user_id = auth.id;
+    let Some(status) = db
+        .interact(move |conn| UserStatus::query(conn, user_id))
+        .await??
+    else {
+        return Ok(html! {});
+    };
+    Ok(html! {
+        @if status.is_banned() {
+            p style="border: 1px solid #c00; padding: 0.5em" {
+                strong { "You have been banned" }
+                @if let Some(banned_at) = status.banned_at {
+                    " since " (banned_at.format("%Y-%m-%d %H:%M"))
+                }
+                "."
+                @if let Some(reason) = &status.ban_reason {
+                    " Reason: " (reason)
+                }
+            }
+        } @else if status.is_muted() {
+            p style="border: 1px solid #c80; padding: 0.5em" {
+                @if let Some(until) = status.muted_until {
+                    strong { "You are muted until " (until.format("%Y-%m-%d %H:%M")) " UTC" }
+                    " and cannot post until then."
+                }
+                @if let Some(reason) = &status.mute_reason {
+                    " Reason: " (reason)
+                }
+            }
+        } @else if status.is_viewer {
+            p style="border: 1px solid #c80; padding: 0.5em" {
+                strong { "Your account is read-only." }
+            }
+        }
+    })
+}
+
+/// Ban and mute status of a user, with the actions a moderator may take
+#[instrument(skip_all, fields(user_id=user_id))]
+pub async fn get_handler(
+    Path(user_id): Path<i64>,
+    auth: UserAuth,
+    csrf: CsrfToken,
+    Extension(db): Extension<Database>,
+) -> Result<impl IntoResponse, ModerationError> {
+    if !(auth.is_moderator() || auth.is_admin()) {
+        return Err(ModerationError::Forbidden(format!(
+            "user {} is not a moderator",
+            auth.id
+        )));
+    }
+    let status = db
+        .interact(move |conn| UserStatus::query(conn, user_id))
+        .await??
+        .ok_or(ModerationError::NotFound(user_id))?;
+    let action = format!("/moderation/users/{}", user_id);
+    Ok(Html(
+        html! {
+            a href="/" { "Index of Reforum" }
+            h1 { "Moderate " (status.username) }
+            ul {
+                @if status.is_admin {
+                    li { "Admin" }
+                }
+                @if status.is_moderator {
+                    li { "Moderator" }
+                }
+                @if status.is_viewer {
+                    li { "Read-only" }
+                }
+                @if status.is_banned() {
+                    li {
+                        "Banned"
+                        @if let Some(banned_at) = status.banned_at {
+                            " since " (banned_at.format("%Y-%m-%d %H:%M"))
+                        }
+                        @if let Some(reason) = &status.ban_reason {
+                            ": " (reason)
+                        }
+                    }
+                }
+                @if status.is_muted() {
+                    li {
+                        "Muted"
+                        @if let Some(until) = status.muted_until {
+                            " until " (until.format("%Y-%m-%d %H:%M"))
+                        }
+                        @if let Some(reason) = &status.mute_reason {
+                            ": " (reason)
+                        }
+                    }
+                }
+                @if !status.is_banned() && !status.is_muted() && !status.is_viewer {
+                    li { "In good standing" }
+                }
+            }
+            @if status.is_banned() {
+                h2 { "Unban" }
+                form method="post" action=(action) {
+                    (csrf)
+                    input type="hidden" name="action" value="unban";
+                    input type="text" name="reason" placeholder="Reason" maxlength="500" required;
+                    " "
+                    button type="submit" { "Unban" }
+                }
+            } @else {
+                h2 { "Ban" }
+                form method="post" action=(action) {
+                    (csrf)
+                    input type="hidden" name="action" value="ban";
+                    input type="text" name="reason" placeholder="Reason" maxlength="500" required;
+                    " "
+                    button type="submit" { "Ban" }
+                }
+            }
+            h2 { "Mute" }
+            form method="post" action=(action) {
+                (csrf)
+                input type="hidden" name="action" value="mute";
+                select name="hours" {
+                    option value="1" { "1 hour" }
+                    option value="24" selected { "1 day" }
+                    option value="168" { "1 week" }
+                    option value="720" { "30 days" }
+                }
+                " "
+                input type="text" name="reason" placeholder="Reason" maxlength="500" required;
+                " "
+                button type="submit" { "Mute" }
+            }
+            @if status.is_muted() {
+                h2 { "Unmute" }
+                form method="post" action=(action) {
+                    (csrf)
+                    input type="hidden" name="action" value="unmute";
+                    input type="text" name="reason" placeholder="Reason" maxlength="500" required;
+                    " "
+                    button type="submit" { "Unmute" }
+                }
+            }
+        }
+        .0,
+    ))
+}
+
+/// Ban, unban, mute or unmute a user
+#[instrument(skip_all, fields(user_id=user_id))]
+pub async fn post_handler(
+    Path(user_id): Path<i64>,
+    auth: UserAuth,
+    Extension(db): Extension<Database>,
+    CsrfForm(form): CsrfForm<SanctionForm>,
+) -> Result<Redirect, ModerationError> {
+    let sanction = match form.action {
+        SanctionAction::Ban => Sanction::Ban,
+        SanctionAction::Unban => Sanction::Unban,
+        SanctionAction::Mute => {
+            let hours = form.hours.unwrap_or(24).clamp(1, MAX_MUTE_HOURS);
+            Sanction::Mute(Duration::hours(hours))
+        }
+        SanctionAction::Unmute => Sanction::Unmute,
+    };
+    sanction.apply(&db, &auth, user_id, &form.reason).await?;
+    Ok(Redirect::to(&format!("/moderation/users/{}", user_id)))
+}
@@ -0,0 +1,193 @@
+use axum::{
+    extract::Query,
+    response::{Html, IntoResponse},
+    Extension,
+};
+use maud::html;
+use serde::{Deserialize, Serialize};
+use tracing::instrument;
+
+use crate::{
+    auth::extractor::UserAuth,
+    model::{
+        database::Database,
+        moderation_log::{LogEntry, LogFilter, ModerationAction},
+        moderator::ModeratorError,
+        user::User,
+    },
+};
+
+const ENTRIES_PER_PAGE: usize = 50;
+
+/// Filters of the log page. Empty fields, as sent by the filter form, match
+/// everything.
+#[derive(Deserialize, Serialize)]
+pub struct LogQuery {
+    #[serde(default)]
+    moderator: String,
+    #[serde(default)]
+    target: String,
+    #[serde(default)]
+    action: String,
+    before: Option<i64>,
+}
+
+impl LogQuery {
+    /// Link to the page of `self`, with every value encoded
+    fn link(&self) -> String {
+        format!(
+            "/admin/log?{}",
+            serde_urlencoded::to_string(self).expect("query of strings and integers")
+        )
+    }
+}
+
+/// The moderation log, newest first
+#[instrument(skip_all)]
+pub async fn handler(
+    auth: UserAuth,
+    Query(query): Query<LogQuery>,
+    Extension(db): Extension<Database>,
+) -> Result<impl IntoResponse, ModeratorError> {
+    if !auth.is_admin() {
+        return Err(ModeratorError::Forbidden(format!(
+            "user {} is not the admin",
+            auth.id
+        )));
+    }
+    let moderator = query.moderator.trim().to_owned();
+    let target = query.target.trim().to_owned();
+    let action = query.action.parse::<ModerationAction>().ok();
+    let before = query.before;
+    let (entries, moderator, target) = db
+        .interact(move |conn| -> Result<_, ModeratorError> {
+            let resolve = |username: &str| -> Result<Option<i64>, ModeratorError> {
+                if username.is_empty() {
+                    return Ok(None);
+                }
+                User::id_by_username(conn, username)?
+                    .map(Some)
+                    .ok_or_else(|| ModeratorError::UnknownUsername(username.to_owned()))
+            };
+            let filter = LogFilter {
+                moderator_user_id: resolve(&moderator)?,
+                target_user_id: resolve(&target)?,
+                action,
+                before_id: before,
+            };
+            Ok((
+                LogEntry::query(conn, &filter, ENTRIES_PER_PAGE)?,
+                moderator,
+                target,
+            ))
+        })
+        .await??;
+    let older = (entries.len() == ENTRIES_PER_PAGE)
+        .then(|| entries.last())
+        .flatten()
+        .map(|last| {
+            LogQuery {
+                moderator: moderator.clone(),
+                target: target.clone(),
+                action: action.map(|a| a.as_str().to_owned()).unwrap_or_default(),
+                before: Some(last.id),
+            }
+            .link()
+        });
+    Ok(Html(
+        html! {
+            a href="/" { "Index of Reforum" }
+            h1 { "Moderation log" }
+            form method="get" action="/admin/log" {
+                input type="text" name="moderator" placeholder="Moderator" value=(moderator);
+                " "
+                input type="text" name="target" placeholder="Target user" value=(target);
+                " "
+                select name="action" {
+                    option value="" { "Any action" }
+                    @for a in ModerationAction::ALL {
+                        option value=(a.as_str()) selected[action == Some(a)] { (a.as_str()) }
+                    }
+                }
+                " "
+                button type="submit" { "Filter" }
+            }
+            @if entries.is_empty() {
+                p { "No entries." }
+            } @else {
+                table {
+                    thead {
+                        tr {
+                            th { "When" }
+                            th { "Moderator" }
+                            th { "Action" }
+                            th { "Target" }
+                            th { "Content" }
+                            th { "Reason" }
+                        }
+                    }
+                    tbody {
+                        @for entry in &entries {
+                            tr {
+                                td { (entry.created_at.format("%Y-%m-%d %H:%M")) }
+                                td {
+                                    @match (&entry.moderator_username, entry.moderator_user_id) {
+                                        (Some(username), Some(id)) => { (username) " (UID " (id) ")" }
+                                        _ => "command line",
+                                    }
+                                }
+                                td { (entry.action.as_str()) }
+                                td {
+                                    @if let (Some(username), Some(id)) = (&entry.target_username, entry.target_user_id) {
+                                        a href=(format!("/moderation/users/{}", id)) { (username) }
+                                        " (UID " (id) ")"
+                                    }
+                                }
+                                td {
+                                    @if let Some(topic_id) = entry.topic_id {
+                                        a href=(format!("/topics/{}", topic_id)) { "topic " (topic_id) }
+                                    }
+                                    @if let Some(post_id) = entry.post_id {
+                                        " " a href=(format!("/posts/{}/revisions", post_id)) { "post " (post_id) }
+                                    }
+                                    @if let Some(reply_id) = entry.reply_id {
+                                        " reply " (reply_id)
+                                    }
+                                }
+                                td { (entry.reason.as_deref().unwrap_or_default()) }
+                            }
+                        }
+                    }
+                }
+            }
+            @if let Some(older) = older {
+                a href=(older) { "Older entries" }
+            }
+        }
+        .0,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn link_round_trips_filters() {
+        let query = LogQuery {
+            moderator: "a&b=c #d".to_owned(),
+            target: "50% é/?".to_owned(),
+            action: "ban".to_owned(),
+            before: Some(42),
+        };
+        let link = query.link();
+        let (path, query_string) = link.split_once('?').unwrap();
+        assert_eq!(path, "/admin/log");
+        assert!(!query_string.contains(['#', ' ']), "{}", query_string);
+        let parsed: LogQuery = serde_urlencoded::from_str(query_string).unwrap();
+        assert_eq!(parsed.moderator, query.moderator);
+        assert_eq!(parsed.target, query.target);
+        assert_eq!(parsed.action, query.action);
+        assert_eq!(parsed.before, Some(42));
+    }
+}
@@ -0,0 +1,156 @@
+use axum::{
+    extract::Path,
+    response::{Html, IntoResponse, Redirect},
+    Extension,
+};
+use maud::html;
+use serde::Deserialize;
+use tracing::instrument;
+
+use crate::{
+    auth::{
+        csrf::{CsrfForm, CsrfToken},
+        extractor::UserAuth,
+    },
+    model::{
+        database::Database,
+        moderator::{Moderator, ModeratorError, PastModerator},
+        user::User,
+    },
+};
+
+#[derive(Deserialize)]
+pub struct PromoteForm {
+    username: String,
+}
+
+#[derive(Deserialize)]
+pub struct DemoteForm {
+    reason: String,
+}
+
+fn require_admin(auth: &UserAuth) -> Result<(), ModeratorError> {
+    if auth.is_admin() {
+        Ok(())
+    } else {
+        Err(ModeratorError::Forbidden(format!(
+            "user {} is not the admin",
+            auth.id
+        )))
+    }
+}
+
+/// Current and past moderators
+#[instrument(skip_all)]
+pub async fn get_handler(
+    auth: UserAuth,
+    csrf: CsrfToken,
+    Extension(db): Extension<Database>,
+) -> Result<impl IntoResponse, ModeratorError> {
+    require_admin(&auth)?;
+    let (moderators, past) = db
+        .interact(|conn| -> Result<_, rusqlite::Error> {
+            Ok((Moderator::list(conn)?, PastModerator::list(conn)?))
+        })
+        .await??;
+    Ok(Html(
+        html! {
+            a href="/" { "Index of Reforum" }
+            h1 { "Moderators" }
+            @if moderators.is_empty() {
+                p { "There are no moderators." }
+            } @else {
+                table {
+                    thead {
+                        tr {
+                            th { "User" }
+                            th { "Since" }
+                            th { "Demote" }
+                        }
+                    }
+                    tbody {
+                        @for moderator in &moderators {
+                            tr {
+                                td { (moderator.username) " (UID " (moderator.user_id) ")" }
+                                td { (moderator.assigned_at.format("%Y-%m-%d %H:%M")) }
+                                td {
+                                    form method="post" action=(format!("/admin/moderators/{}/demote", moderator.user_id)) {
+                                        (csrf)
+                                        input type="text" name="reason" placeholder="Reason" maxlength="500" required;
+                                        " "
+                                        button type="submit" { "Demote" }
+                                    }
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+            h2 { "Promote" }
+            form method="post" action="/admin/moderators" {
+                (csrf)
+                input type="text" name="username" placeholder="Username" required;
+                " "
+                button type="submit" { "Promote to moderator" }
+            }
+            h2 { "Past moderators" }
+            @if past.is_empty() {
+                p { "Nobody has been demoted." }
+            } @else {
+                table {
+                    thead {
+                        tr {
+                            th { "User" }
+                            th { "Until" }
+                            th { "Reason" }
+                        }
+                    }
+                    tbody {
+                        @for past_moderator in &past {
+                            tr {
+                                td { (past_moderator.username) " (UID " (past_moderator.user_id) ")" }
+                                td { (past_moderator.unassigned_at.format("%Y-%m-%d %H:%M")) }
+                                td { (past_moderator.reason) }
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        .0,
+    ))
+}
+
+/// Promote a user to moderator
+#[instrument(skip_all, fields(username=form.username))]
+pub async fn promote_handler(
+    auth: UserAuth,
+    Extension(db): Extension<Database>,
+    CsrfForm(form): CsrfForm<PromoteForm>,
+) -> Result<Redirect, ModeratorError> {
+    require_admin(&auth)?;
+    let admin_user_id = auth.id;
+    db.interact(move |conn| -> Result<(), ModeratorError> {
+        let username = form.username.trim();
+        let user_id = User::id_by_username(conn, username)?
+            .ok_or_else(|| ModeratorError::UnknownUsername(username.to_owned()))?;
+        Moderator::promote(conn, Some(admin_user_id), user_id)
+    })
+    .await??;
+    Ok(Redirect::to("/admin/moderators"))
+}
+
+/// Demote a moderator
+#[instrument(skip_all, fields(user_id=user_id))]
+pub async fn demote_handler(
+    Path(user_id): Path<i64>,
+    auth: UserAuth,
+    Extension(db): Extension<Database>,
+    CsrfForm(form): CsrfForm<DemoteForm>,
+) -> Result<Redirect, ModeratorError> {
+    require_admin(&auth)?;
+    let admin_user_id = auth.id;
+    db.interact(move |conn| Moderator::demote(conn, Some(admin_user_id), user_id, &form.reason))
+        .await??;
+    Ok(Redirect::to("/admin/moderators"))
+}
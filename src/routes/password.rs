@@ -0,0 +1,285 @@
+use std::net::SocketAddr;
+
+use axum::{
+    extract::{ConnectInfo, Path},
+    http::{HeaderMap, StatusCode},
+    response::{Html, IntoResponse, Redirect, Response},
+    Extension,
+};
+use axum_sessions::SessionHandle;
+use chrono::{Duration, Utc};
+use maud::html;
+use serde::Deserialize;
+use tracing::instrument;
+use validator::ValidationErrorsKind;
+
+use crate::{
+    auth::{
+        csrf::{CsrfForm, CsrfToken},
+        extractor::UserAuth,
+        password::{ChangePasswordForm, PasswordError, PasswordReset, ResetPasswordForm},
+    },
+    configuration::{AccountSettings, LoginSettings, PasswordHashSettings},
+    model::database::Database,
+};
+
+use super::{login::client_ip, register::validation_message};
+
+impl IntoResponse for PasswordError {
+    fn into_response(self) -> Response {
+        match self {
+            PasswordError::IncorrectPassword => {
+                (StatusCode::FORBIDDEN, "Current password is incorrect").into_response()
+            }
+            PasswordError::Throttled(retry_after) => {
+                let secs = (retry_after - Utc::now()).num_seconds().max(1);
+                (
+                    StatusCode::TOO_MANY_REQUESTS,
+                    [("Retry-After", secs.to_string())],
+                    format!(
+                        "Too many failed attempts, try again after {} UTC",
+                        retry_after.format("%Y-%m-%d %H:%M:%S")
+                    ),
+                )
+                    .into_response()
+            }
+            PasswordError::InvalidToken => (
+                StatusCode::NOT_FOUND,
+                "This password reset link is invalid, expired or already used",
+            )
+                .into_response(),
+            PasswordError::UnknownUsername(username) => (
+                StatusCode::NOT_FOUND,
+                format!("No user named `{}`", username),
+            )
+                .into_response(),
+            PasswordError::Forbidden(_) => (StatusCode::FORBIDDEN, "403 forbidden").into_response(),
+            PasswordError::Invalid(errors) => {
+                let messages = errors
+                    .errors()
+                    .values()
+                    .flat_map(|kind| match kind {
+                        ValidationErrorsKind::Field(errs) => errs.as_slice(),
+                        _ => &[],
+                    })
+                    .map(|e| validation_message(&e.code))
+                    .collect::<Vec<_>>();
+                (
+                    StatusCode::BAD_REQUEST,
+                    Html(
+                        html! {
+                            h1{"Password not changed"}
+                            ul {
+                                @for message in messages {
+                                    li { (message) }
+                                }
+                            }
+                            p { "Go back and try again." }
+                        }
+                        .0,
+                    ),
+                )
+                    .into_response()
+            }
+            PasswordError::InternalError
+            | PasswordError::AuthenticationError(_)
+            | PasswordError::RusqliteError(_)
+            | PasswordError::InteractError(_) => (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "500 Internal Server Error",
+            )
+                .into_response(),
+        }
+    }
+}
+
+/// Page to change the viewer's own password
+#[instrument(skip_all)]
+pub async fn change_get_handler(_auth: UserAuth, csrf: CsrfToken) -> impl IntoResponse {
+    Html(
+        html! {
+            a href="/" { "Index of Reforum" }
+            h1 { "Change password" }
+            p { "Every other device logged in to your account will be logged out." }
+            form method="post" action="/account/password" {
+                (csrf)
+                div {
+                    label for="current_password" { "Current password" }
+                    input type="password" id="current_password" name="current_password" required;
+                }
+                div {
+                    label for="new_password" { "New password" }
+                    input type="password" id="new_password" name="new_password" required;
+                }
+                div {
+                    label for="new_password_confirmation" { "Confirm new password" }
+                    input type="password" id="new_password_confirmation" name="new_password_confirmation" required;
+                }
+                button type="submit" { "Change password" }
+            }
+        }
+        .0,
+    )
+}
+
+/// Change the viewer's own password, logging out their other sessions
+#[allow(clippy::too_many_arguments)]
+#[instrument(skip_all)]
+pub async fn change_post_handler(
+    auth: UserAuth,
+    Extension(session): Extension<SessionHandle>,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    Extension(db): Extension<Database>,
+    Extension(settings): Extension<LoginSettings>,
+    Extension(hash_settings): Extension<PasswordHashSettings>,
+    CsrfForm(form): CsrfForm<ChangePasswordForm>,
+) -> Result<impl IntoResponse, PasswordError> {
+    let ip = client_ip(&settings, &headers, peer).to_string();
+    let session_id = session.read().await.id().to_owned();
+    let sessions = form
+        .change(&db, &settings, hash_settings, auth.id, session_id, ip)
+        .await?;
+    Ok(Html(
+        html! {
+            h1 { "Password changed" }
+            p {
+                @if sessions == 1 {
+                    "1 other session was logged out."
+                } @else {
+                    (sessions) " other sessions were logged out."
+                }
+            }
+            a href="/" { "Index of Reforum" }
+        }
+        .0,
+    ))
+}
+
+/// Page to choose a new password through a reset link
+#[instrument(skip_all)]
+pub async fn reset_get_handler(
+    Path(token): Path<String>,
+    csrf: CsrfToken,
+    Extension(db): Extension<Database>,
+) -> Result<impl IntoResponse, PasswordError> {
+    let lookup = token.clone();
+    let reset = db
+        .interact(move |conn| PasswordReset::query_by_token(conn, &lookup))
+        .await??
+        .ok_or(PasswordError::InvalidToken)?;
+    Ok(Html(
+        html! {
+            h1 { "Reset password" }
+            p {
+                "This link expires on " (reset.expires_at.format("%Y-%m-%d %H:%M")) " UTC "
+                "and can only be used once. Every device logged in to your account will be logged out."
+            }
+            form method="post" action=(format!("/password-reset/{}", token)) {
+                (csrf)
+                div {
+                    label for="password" { "New password" }
+                    input type="password" id="password" name="password" required;
+                }
+                div {
+                    label for="password_confirmation" { "Confirm new password" }
+                    input type="password" id="password_confirmation" name="password_confirmation" required;
+                }
+                button type="submit" { "Reset password" }
+            }
+        }
+        .0,
+    ))
+}
+
+/// Set a new password through a reset link
+#[instrument(skip_all)]
+pub async fn reset_post_handler(
+    Path(token): Path<String>,
+    auth: Option<UserAuth>,
+    Extension(session): Extension<SessionHandle>,
+    Extension(db): Extension<Database>,
+    Extension(hash_settings): Extension<PasswordHashSettings>,
+    CsrfForm(form): CsrfForm<ResetPasswordForm>,
+) -> Result<Redirect, PasswordError> {
+    let user_id = PasswordReset::redeem(&db, hash_settings, token, &form).await?;
+    // The session row is gone, but would be stored again at the end of the
+    // request
+    if auth.map(|auth| auth.id) == Some(user_id) {
+        session.write().await.destroy();
+    }
+    Ok(Redirect::to("/login"))
+}
+
+fn require_admin(auth: &UserAuth) -> Result<(), PasswordError> {
+    if auth.is_admin() {
+        Ok(())
+    } else {
+        Err(PasswordError::Forbidden(format!(
+            "user {} is not the admin",
+            auth.id
+        )))
+    }
+}
+
+#[derive(Deserialize)]
+pub struct IssueForm {
+    username: String,
+}
+
+/// Page to issue a password reset link
+#[instrument(skip_all)]
+pub async fn issue_get_handler(
+    auth: UserAuth,
+    csrf: CsrfToken,
+) -> Result<impl IntoResponse, PasswordError> {
+    require_admin(&auth)?;
+    Ok(Html(
+        html! {
+            a href="/" { "Index of Reforum" }
+            h1 { "Password reset links" }
+            p {
+                "A link lets the user choose a new password once. "
+                "Issuing a link revokes the user's previous unused links."
+            }
+            form method="post" action="/admin/password-resets" {
+                (csrf)
+                input type="text" name="username" placeholder="Username" required;
+                " "
+                button type="submit" { "Issue link" }
+            }
+        }
+        .0,
+    ))
+}
+
+/// Issue a password reset link, shown only once
+#[instrument(skip_all, fields(username=form.username))]
+pub async fn issue_post_handler(
+    auth: UserAuth,
+    Extension(db): Extension<Database>,
+    Extension(settings): Extension<AccountSettings>,
+    CsrfForm(form): CsrfForm<IssueForm>,
+) -> Result<impl IntoResponse, PasswordError> {
+    require_admin(&auth)?;
+    let admin_user_id = auth.id;
+    let username = form.username.trim().to_owned();
+    let valid_for = Duration::seconds(settings.password_reset_secs as i64);
+    let (reset, token) = db
+        .transaction(move |tx| PasswordReset::issue(tx, Some(admin_user_id), &username, valid_for))
+        .await??;
+    let link = format!("/password-reset/{}", token);
+    Ok(Html(
+        html! {
+            a href="/admin/password-resets" { "Password reset links" }
+            h1 { "Link issued" }
+            p {
+                "Send this link to " (form.username.trim()) " (UID " (reset.user_id) "). "
+                "It will not be shown again, and expires on "
+                (reset.expires_at.format("%Y-%m-%d %H:%M")) " UTC."
+            }
+            p { code { (link) } }
+        }
+        .0,
+    ))
+}
@@ -0,0 +1,139 @@
+use axum::{
+    extract::Path,
+    response::{Html, IntoResponse, Redirect},
+    Extension,
+};
+use maud::html;
+use tracing::instrument;
+use validator::Validate;
+
+use super::revisions::history_markup;
+use super::topics::{page_url, PostForm};
+use crate::{
+    auth::{
+        csrf::{CsrfCheck, CsrfForm, CsrfToken},
+        extractor::UserAuth,
+    },
+    model::{
+        database::Database,
+        post::Post,
+        revision::Revision,
+        topic::{Topic, TopicError},
+        user::User,
+    },
+};
+
+/// Form to edit a post
+#[instrument(skip_all, fields(id=id))]
+pub async fn edit_get_handler(
+    Path(id): Path<i64>,
+    auth: UserAuth,
+    csrf: CsrfToken,
+    Extension(db): Extension<Database>,
+) -> Result<impl IntoResponse, TopicError> {
+    let post = Post::query(&db, Some(&auth), id).await?;
+    let topic = Topic::query(&db, Some(&auth), post.topic_id).await?;
+    if !auth.can_edit(post.author_user_id) {
+        return Err(TopicError::Forbidden(format!(
+            "user {} cannot edit post {}",
+            auth.id, id
+        )));
+    }
+    Ok(Html(
+        html! {
+            a href=(format!("{}#post-{}", page_url(&post), post.post_number)) { (topic.title) }
+            h1 { "Edit post #" (post.post_number) }
+            form method="post" action=(format!("/posts/{}/edit", post.id)) {
+                (csrf)
+                div {
+                    label for="body" { "Message" }
+                    br;
+                    textarea id="body" name="body" rows="10" cols="80" required { (post.body) }
+                }
+                button type="submit" { "Save" }
+            }
+        }
+        .0,
+    ))
+}
+
+/// Edit a post
+#[instrument(skip_all, fields(id=id))]
+pub async fn edit_post_handler(
+    Path(id): Path<i64>,
+    auth: UserAuth,
+    Extension(db): Extension<Database>,
+    CsrfForm(form): CsrfForm<PostForm>,
+) -> Result<Redirect, TopicError> {
+    form.validate()?;
+    let post = Post::query(&db, Some(&auth), id).await?;
+    let topic = Topic::query(&db, Some(&auth), post.topic_id).await?;
+    let post = post.edit(&db, &auth, &topic, &form.body).await?;
+    Ok(Redirect::to(&format!(
+        "{}#post-{}",
+        page_url(&post),
+        post.post_number
+    )))
+}
+
+/// Edit history of a post
+#[instrument(skip_all, fields(id=id))]
+pub async fn revisions_handler(
+    Path(id): Path<i64>,
+    auth: Option<UserAuth>,
+    Extension(db): Extension<Database>,
+) -> Result<impl IntoResponse, TopicError> {
+    let post = Post::query(&db, auth.as_ref(), id).await?;
+    let topic = Topic::query(&db, auth.as_ref(), post.topic_id).await?;
+    let (revisions, editors) = db
+        .interact(move |conn| -> Result<_, rusqlite::Error> {
+            let revisions = Revision::query_by_post_id(conn, id)?;
+            let editors = User::query_many(conn, revisions.iter().map(|r| r.edited_by))?;
+            Ok((revisions, editors))
+        })
+        .await??;
+    Ok(Html(
+        html! {
+            a href=(format!("{}#post-{}", page_url(&post), post.post_number)) { (topic.title) }
+            h1 { "History of post #" (post.post_number) }
+            (history_markup(&revisions, &post.body, &editors))
+        }
+        .0,
+    ))
+}
+
+/// Soft-delete a post
+#[instrument(skip_all, fields(id=id))]
+pub async fn delete_handler(
+    Path(id): Path<i64>,
+    auth: UserAuth,
+    Extension(db): Extension<Database>,
+    _: CsrfCheck,
+) -> Result<Redirect, TopicError> {
+    let post = Post::query(&db, Some(&auth), id).await?;
+    Topic::query(&db, Some(&auth), post.topic_id).await?;
+    let post = post.delete(&db, &auth).await?;
+    Ok(Redirect::to(&format!(
+        "{}#post-{}",
+        page_url(&post),
+        post.post_number
+    )))
+}
+
+/// Restore a soft-deleted post
+#[instrument(skip_all, fields(id=id))]
+pub async fn restore_handler(
+    Path(id): Path<i64>,
+    auth: UserAuth,
+    Extension(db): Extension<Database>,
+    _: CsrfCheck,
+) -> Result<Redirect, TopicError> {
+    let post = Post::query(&db, Some(&auth), id).await?;
+    Topic::query(&db, Some(&auth), post.topic_id).await?;
+    let post = post.restore(&db, &auth).await?;
+    Ok(Redirect::to(&format!(
+        "{}#post-{}",
+        page_url(&post),
+        post.post_number
+    )))
+}
@@ -0,0 +1,156 @@
+use axum::{
+    http::StatusCode,
+    response::{Html, IntoResponse, Redirect, Response},
+    Extension,
+};
+use axum_sessions::SessionHandle;
+use maud::html;
+use thiserror::Error;
+use tracing::instrument;
+use validator::ValidationErrorsKind;
+
+use crate::auth::csrf::{self, CsrfForm, CsrfToken};
+use crate::auth::extractor::UserAuth;
+use crate::auth::registration::{RegistrationError, RegistrationForm};
+use crate::configuration::{PasswordHashSettings, RegistrationPolicy, RegistrationSettings};
+use crate::model::database::Database;
+
+#[derive(Error, Debug)]
+pub enum RegisterError {
+    #[error("forbidden")]
+    AlreadyLoggedIn,
+    #[error(transparent)]
+    RegistrationError(#[from] RegistrationError),
+    #[error("session error")]
+    SessionError(#[from] serde_json::Error),
+}
+
+impl IntoResponse for RegisterError {
+    fn into_response(self) -> Response {
+        match self {
+            RegisterError::AlreadyLoggedIn => {
+                (StatusCode::FORBIDDEN, "Already logged in").into_response()
+            }
+            RegisterError::RegistrationError(err) => match err {
+                RegistrationError::Closed => {
+                    (StatusCode::FORBIDDEN, "Registration is closed").into_response()
+                }
+                RegistrationError::InvalidInvite => {
+                    (StatusCode::FORBIDDEN, "Invalid invite code").into_response()
+                }
+                RegistrationError::UsernameTaken => {
+                    (StatusCode::CONFLICT, "Username is taken").into_response()
+                }
+                RegistrationError::Invalid(errors) => {
+                    let messages = errors
+                        .errors()
+                        .values()
+                        .flat_map(|kind| match kind {
+                            ValidationErrorsKind::Field(errs) => errs.as_slice(),
+                            _ => &[],
+                        })
+                        .map(|e| validation_message(&e.code))
+                        .collect::<Vec<_>>();
+                    (
+                        StatusCode::BAD_REQUEST,
+                        Html(
+                            html! {
+                                h1{"Registration failed"}
+                                ul {
+                                    @for message in messages {
+                                        li { (message) }
+                                    }
+                                }
+                                a href="/register" { "Try again" }
+                            }
+                            .0,
+                        ),
+                    )
+                        .into_response()
+                }
+                RegistrationError::InternalError | RegistrationError::RusqliteError(_) => (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    "500 Internal Server Error",
+                )
+                    .into_response(),
+            },
+            RegisterError::SessionError(_) => (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "500 Internal Server Error",
+            )
+                .into_response(),
+        }
+    }
+}
+
+pub fn validation_message(code: &str) -> &'static str {
+    match code {
+        "length" => "Username must be between 3 and 32 characters",
+        "username_characters" => "Username may only contain letters, digits, `-` and `_`",
+        "password_length" => "Password must be between 8 and 128 characters",
+        "password_confirmation" => "Passwords do not match",
+        _ => "Invalid input",
+    }
+}
+
+#[instrument(skip_all)]
+pub async fn get_handler(
+    auth: Option<UserAuth>,
+    csrf: CsrfToken,
+    Extension(settings): Extension<RegistrationSettings>,
+) -> Result<impl IntoResponse, RegisterError> {
+    if auth.is_some() {
+        return Err(RegisterError::AlreadyLoggedIn);
+    }
+    if settings.policy == RegistrationPolicy::Closed {
+        return Err(RegistrationError::Closed.into());
+    }
+    Ok(Html(
+        html! {
+            h1{"Register"}
+            form method="post" {
+                (csrf)
+                div {
+                    label for="username" { "Username" }
+                    input type="text" name="username";
+                }
+                div {
+                    label for="password" { "Password" }
+                    input type="password" name="password";
+                }
+                div {
+                    label for="password_confirmation" { "Confirm password" }
+                    input type="password" name="password_confirmation";
+                }
+                @if settings.policy == RegistrationPolicy::Invite {
+                    div {
+                        label for="invite_code" { "Invite code" }
+                        input type="text" name="invite_code";
+                    }
+                }
+                button type="submit" { "Register" }
+            }
+        }
+        .0,
+    ))
+}
+
+#[instrument(skip_all, fields(username=form.username))]
+pub async fn post_handler(
+    auth: Option<UserAuth>,
+    Extension(session): Extension<SessionHandle>,
+    Extension(settings): Extension<RegistrationSettings>,
+    Extension(hash_settings): Extension<PasswordHashSettings>,
+    Extension(db): Extension<Database>,
+    CsrfForm(form): CsrfForm<RegistrationForm>,
+) -> Result<Redirect, RegisterError> {
+    if auth.is_some() {
+        return Err(RegisterError::AlreadyLoggedIn);
+    }
+    let user_id = form.register(&db, &settings, hash_settings).await?;
+    let mut session = session.write().await;
+    session.regenerate();
+    csrf::reset(&mut session);
+    session.insert("uid", user_id)?;
+    Ok(Redirect::to("/"))
+}
@@ -0,0 +1,64 @@
+use axum::{extract::Path, response::Redirect, Extension};
+use serde::Deserialize;
+use tracing::instrument;
+use validator::Validate;
+
+use super::topics::{page_url, validate_not_blank, PostingError};
+use crate::{
+    auth::{
+        csrf::{CsrfCheck, CsrfForm},
+        extractor::UserAuth,
+    },
+    configuration::PostingSettings,
+    model::{
+        database::Database,
+        post::Post,
+        reply::Reply,
+        topic::{Topic, TopicError},
+    },
+};
+
+#[derive(Deserialize, Validate)]
+pub struct ReplyForm {
+    #[validate(length(max = 1000), custom = "validate_not_blank")]
+    body: String,
+}
+
+/// Reply to a post
+#[instrument(skip_all, fields(post_id=post_id))]
+pub async fn post_handler(
+    Path(post_id): Path<i64>,
+    auth: UserAuth,
+    Extension(db): Extension<Database>,
+    Extension(settings): Extension<PostingSettings>,
+    CsrfForm(form): CsrfForm<ReplyForm>,
+) -> Result<Redirect, PostingError> {
+    form.validate()?;
+    let post = Post::query(&db, Some(&auth), post_id).await?;
+    let topic = Topic::query(&db, Some(&auth), post.topic_id).await?;
+    let reply = Reply::insert(&db, &auth, settings, &topic, &post, &form.body).await?;
+    Ok(Redirect::to(&format!(
+        "{}#reply-{}",
+        page_url(&post),
+        reply.id
+    )))
+}
+
+/// Permanently delete a reply
+#[instrument(skip_all, fields(id=id))]
+pub async fn delete_handler(
+    Path(id): Path<i64>,
+    auth: UserAuth,
+    Extension(db): Extension<Database>,
+    _: CsrfCheck,
+) -> Result<Redirect, TopicError> {
+    let reply = Reply::query(&db, id).await?;
+    let post = Post::query(&db, Some(&auth), reply.post_id).await?;
+    Topic::query(&db, Some(&auth), post.topic_id).await?;
+    reply.delete(&db, &auth).await?;
+    Ok(Redirect::to(&format!(
+        "{}#post-{}",
+        page_url(&post),
+        post.post_number
+    )))
+}
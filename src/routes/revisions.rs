@@ -0,0 +1,51 @@
+use std::collections::HashMap;
+
+use maud::{html, Markup};
+use similar::{ChangeTag, TextDiff};
+
+use crate::model::{revision::Revision, user::User};
+
+/// Word-level diff from `old` to `new`
+fn diff_markup(old: &str, new: &str) -> Markup {
+    let diff = TextDiff::from_words(old, new);
+    html! {
+        p style="white-space: pre-wrap" {
+            @for change in diff.iter_all_changes() {
+                @match change.tag() {
+                    ChangeTag::Equal => (change.value()),
+                    ChangeTag::Delete => del style="background: #fdd" { (change.value()) },
+                    ChangeTag::Insert => ins style="background: #dfd" { (change.value()) },
+                }
+            }
+        }
+    }
+}
+
+/// Lists every edit, newest first, as a diff against the version it replaced.
+/// `revisions` are oldest first and `current` is the content as of now.
+pub(super) fn history_markup(
+    revisions: &[Revision],
+    current: &str,
+    editors: &HashMap<i64, User>,
+) -> Markup {
+    let newer = revisions
+        .iter()
+        .skip(1)
+        .map(|r| r.content.as_str())
+        .chain(std::iter::once(current));
+    let mut edits = revisions.iter().zip(newer).collect::<Vec<_>>();
+    edits.reverse();
+    html! {
+        @if revisions.is_empty() {
+            p { "Never edited." }
+        }
+        @for (revision, new) in edits {
+            h2 {
+                "Edited by "
+                (editors.get(&revision.edited_by).map(|u| u.username.as_str()).unwrap_or("[unknown]"))
+                " on " (revision.created_at.format("%Y-%m-%d %H:%M"))
+            }
+            (diff_markup(&revision.content, new))
+        }
+    }
+}
@@ -1,38 +1,520 @@
 use axum::{
     extract::{Path, Query},
-    response::IntoResponse,
+    http::StatusCode,
+    response::{Html, IntoResponse, Redirect, Response},
     *,
 };
-use deadpool_sqlite::Pool;
+use chrono::{DateTime, Utc};
+use maud::{html, Markup};
+use serde::Deserialize;
+use validator::{Validate, ValidationError, ValidationErrors};
 
 use tracing::instrument;
 
-use crate::auth::{
-    filter::Pagination,
-    session::Session,
-    topic::{Topic, TopicError},
+use super::moderation::status_notice;
+use super::revisions::history_markup;
+
+use crate::{
+    auth::{
+        csrf::{CsrfCheck, CsrfForm, CsrfToken},
+        extractor::UserAuth,
+    },
+    configuration::PostingSettings,
+    model::{
+        database::Database,
+        post::{Post, PostError},
+        reply::{Reply, ReplyError},
+        revision::Revision,
+        topic::{Topic, TopicError},
+        user::User,
+    },
 };
 
+const POSTS_PER_PAGE: i64 = 20;
+
+#[derive(Deserialize, Validate)]
+pub struct NewTopicForm {
+    #[validate(length(max = 200), custom = "validate_not_blank")]
+    title: String,
+    #[validate(length(max = 65536), custom = "validate_not_blank")]
+    body: String,
+    /// Checkbox, present when the topic should only be visible to its author
+    /// and moderators
+    hidden: Option<String>,
+}
+
+#[derive(Deserialize, Validate)]
+pub struct PostForm {
+    #[validate(length(max = 65536), custom = "validate_not_blank")]
+    pub(super) body: String,
+}
+
+#[derive(Deserialize, Validate)]
+pub struct TitleForm {
+    #[validate(length(max = 200), custom = "validate_not_blank")]
+    title: String,
+}
+
+pub(super) fn validate_not_blank(text: &str) -> Result<(), ValidationError> {
+    if text.trim().is_empty() {
+        Err(ValidationError::new("blank"))
+    } else {
+        Ok(())
+    }
+}
+
+/// Links to the page of the topic that contains `post`
+pub(super) fn page_url(post: &Post) -> String {
+    format!(
+        "/topics/{}?page={}",
+        post.topic_id,
+        post.post_number / POSTS_PER_PAGE + 1
+    )
+}
+
+/// Errors of the handlers creating topics, posts and replies. Posting too
+/// soon renders a page telling the user when they may post again.
+pub struct PostingError(TopicError);
+
+impl From<TopicError> for PostingError {
+    fn from(e: TopicError) -> Self {
+        Self(e)
+    }
+}
+
+impl From<PostError> for PostingError {
+    fn from(e: PostError) -> Self {
+        Self(e.into())
+    }
+}
+
+impl From<ReplyError> for PostingError {
+    fn from(e: ReplyError) -> Self {
+        Self(e.into())
+    }
+}
+
+impl From<ValidationErrors> for PostingError {
+    fn from(e: ValidationErrors) -> Self {
+        Self(e.into())
+    }
+}
+
+impl IntoResponse for PostingError {
+    fn into_response(self) -> Response {
+        match self.0 {
+            TopicError::PostError(PostError::TooSoon(retry_at))
+            | TopicError::ReplyError(ReplyError::TooSoon(retry_at)) => too_soon_response(retry_at),
+            e => e.into_response(),
+        }
+    }
+}
+
+fn too_soon_response(retry_at: DateTime<Utc>) -> Response {
+    let secs = (retry_at - Utc::now()).num_seconds().max(1);
+    (
+        StatusCode::TOO_MANY_REQUESTS,
+        [("Retry-After", secs.to_string())],
+        Html(
+            html! {
+                h1 { "Slow down" }
+                p {
+                    "You are posting too quickly. You can post again in "
+                    (secs) @if secs == 1 { " second" } @else { " seconds" }
+                    ", at " (retry_at.format("%H:%M:%S")) " UTC."
+                }
+                p { "Go back to keep your message, and submit it again then." }
+                a href="/" { "Index of Reforum" }
+            }
+            .0,
+        ),
+    )
+        .into_response()
+}
+
+fn body_textarea() -> Markup {
+    html! {
+        div {
+            label for="body" { "Message" }
+            br;
+            textarea id="body" name="body" rows="10" cols="80" required {}
+        }
+    }
+}
+
+/// A button that POSTs to `action`, laid out inline with text
+fn action_button(csrf: &CsrfToken, action: &str, label: &str) -> Markup {
+    html! {
+        form method="post" action=(action) style="display: inline" {
+            (csrf)
+            button type="submit" { (label) }
+        }
+    }
+}
+
+fn reply_markup(
+    csrf: &CsrfToken,
+    reply: &Reply,
+    author: Option<&User>,
+    auth: Option<&UserAuth>,
+) -> Markup {
+    let can_delete = auth.map(|a| a.can_edit(reply.author_user_id)) == Some(true);
+    html! {
+        li id=(format!("reply-{}", reply.id)) {
+            (author.map(|u| u.username.as_str()).unwrap_or("[unknown]"))
+            " on " (reply.created_at.format("%Y-%m-%d %H:%M")) ": "
+            span style="white-space: pre-wrap" { (reply.body) }
+            @if can_delete {
+                " "
+                (action_button(csrf, &format!("/replies/{}/delete", reply.id), "Delete"))
+            }
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct TopicPageQuery {
+    /// Page number, starting from 1
+    page: Option<i64>,
+}
+
 /// Get a topic
 #[instrument(skip_all,fields(id=id))]
 pub async fn get_handler(
     Path(id): Path<i64>,
-    Query(_pagination): Query<Pagination>,
-    session: Session,
-    Extension(db): Extension<Pool>,
+    Query(query): Query<TopicPageQuery>,
+    auth: Option<UserAuth>,
+    csrf: CsrfToken,
+    Extension(db): Extension<Database>,
 ) -> Result<impl IntoResponse, TopicError> {
-    let db = db.get().await.map_err(|_| TopicError::DeadpoolError)?;
+    let topic = Topic::query(&db, auth.as_ref(), id).await?;
+    let pages = ((topic.next_post_number + POSTS_PER_PAGE - 1) / POSTS_PER_PAGE).max(1);
+    let page = query.page.unwrap_or(1).clamp(1, pages);
+    let posts = topic
+        .posts(&db, auth.as_ref(), page - 1, POSTS_PER_PAGE)
+        .await?;
+    let author = topic.author(&db).await?;
+    let last_updated_by = topic.last_updated_by(&db).await?;
+    let replies = Reply::query_by_posts(&db, auth.as_ref(), &posts).await?;
+    let author_ids = posts
+        .iter()
+        .map(|p| p.author_user_id)
+        .chain(replies.values().flatten().map(|r| r.author_user_id))
+        .collect::<Vec<_>>();
+    let can_post = topic.deleted_at.is_none() && auth.as_ref().map(|a| a.can_post()) == Some(true);
+    let can_edit = |author_user_id| {
+        topic.deleted_at.is_none()
+            && auth.as_ref().map(|a| a.can_edit(author_user_id)) == Some(true)
+    };
+    let privileged = auth.as_ref().map(|a| a.is_moderator() || a.is_admin()) == Some(true);
+    let authors = db
+        .interact(move |conn| User::query_many(conn, author_ids))
+        .await??;
+    let notice = status_notice(&db, auth.as_ref()).await?;
 
-    let topic = Topic::query(&db, &session, id).await?;
-    Ok(format!("Got topic {}: {}", topic.id, topic.title))
+    Ok(Html(
+        html! {
+            a href="/" { "Index of Reforum" }
+            (notice)
+            h1 {
+                (topic.title)
+                @if topic.deleted_at.is_some() {
+                    " [deleted]"
+                } @else if !topic.public {
+                    " [hidden]"
+                }
+            }
+            p {
+                "Started by " (author.username)
+                " on " (topic.created_at.format("%Y-%m-%d %H:%M"))
+                @if let Some(editor) = &last_updated_by {
+                    ", last edited by " (editor.username)
+                    " (" a href=(format!("/topics/{}/revisions", topic.id)) { "history" } ")"
+                }
+                @if can_edit(topic.author_user_id) {
+                    " "
+                    a href=(format!("/topics/{}/edit", topic.id)) { "Edit title" }
+                    " "
+                    (action_button(&csrf, &format!("/topics/{}/delete", topic.id), "Delete topic"))
+                }
+            }
+            @if let Some(deleted_at) = topic.deleted_at {
+                p {
+                    strong { "This topic was deleted on " (deleted_at.format("%Y-%m-%d %H:%M")) "." }
+                    @if privileged {
+                        " "
+                        (action_button(&csrf, &format!("/topics/{}/restore", topic.id), "Restore topic"))
+                    }
+                }
+            }
+            @for post in &posts {
+                @let post_author = authors.get(&post.author_user_id);
+                div id=(format!("post-{}", post.post_number)) {
+                    p {
+                        a href=(format!("#post-{}", post.post_number)) {
+                            "#" (post.post_number)
+                        }
+                        " by " (post_author.map(|u| u.username.as_str()).unwrap_or("[unknown]"))
+                        @if privileged {
+                            " "
+                            a href=(format!("/moderation/users/{}", post.author_user_id)) { "[moderate]" }
+                        }
+                        " on " (post.created_at.format("%Y-%m-%d %H:%M"))
+                        @if post.deleted_at.is_some() {
+                            " [deleted]"
+                            @if privileged {
+                                " "
+                                (action_button(&csrf, &format!("/posts/{}/restore", post.id), "Restore"))
+                            }
+                        } @else if !post.public {
+                            " [hidden]"
+                        }
+                        @if post.last_updated_by.is_some() {
+                            " "
+                            a href=(format!("/posts/{}/revisions", post.id)) { "[edited]" }
+                        }
+                        @if post.deleted_at.is_none() && can_edit(post.author_user_id) {
+                            " "
+                            a href=(format!("/posts/{}/edit", post.id)) { "Edit" }
+                            " "
+                            (action_button(&csrf, &format!("/posts/{}/delete", post.id), "Delete"))
+                        }
+                    }
+                    @if let Some(deleted_at) = post.deleted_at {
+                        details {
+                            summary { "Deleted on " (deleted_at.format("%Y-%m-%d %H:%M")) }
+                            p style="white-space: pre-wrap" { (post.body) }
+                        }
+                    } @else {
+                        p style="white-space: pre-wrap" { (post.body) }
+                        @if let Some(signature) = post_author.and_then(|u| u.post_signature.as_ref()) {
+                            p style="white-space: pre-wrap" { "-- " br; (signature) }
+                        }
+                    }
+                    @if let Some(replies) = replies.get(&post.id) {
+                        ul {
+                            @for reply in replies {
+                                (reply_markup(&csrf, reply, authors.get(&reply.author_user_id), auth.as_ref()))
+                            }
+                        }
+                    }
+                    @if can_post && post.deleted_at.is_none() {
+                        details {
+                            summary { "Reply to #" (post.post_number) }
+                            form method="post" action=(format!("/posts/{}/replies", post.id)) {
+                                (csrf)
+                                input type="text" name="body" size="80" maxlength="1000" required;
+                                " "
+                                button type="submit" { "Reply" }
+                            }
+                        }
+                    }
+                }
+                hr;
+            }
+            @if pages > 1 {
+                p {
+                    "Page "
+                    @for p in 1..=pages {
+                        @if p == page {
+                            strong { (p) }
+                        } @else {
+                            a href=(format!("/topics/{}?page={}", topic.id, p)) { (p) }
+                        }
+                        " "
+                    }
+                }
+            }
+            @if can_post {
+                h2 { "Reply" }
+                form method="post" action=(format!("/topics/{}", topic.id)) {
+                    (csrf)
+                    (body_textarea())
+                    button type="submit" { "Post reply" }
+                }
+            }
+        }
+        .0,
+    ))
 }
 
 /// Post a new reply to a topic
 #[instrument(skip_all, fields(id=id))]
 pub async fn post_handler(
     Path(id): Path<i64>,
-    _session: Session,
-    Extension(_db): Extension<Pool>,
-) -> impl IntoResponse {
-    "Not impleneted"
+    auth: UserAuth,
+    Extension(db): Extension<Database>,
+    Extension(settings): Extension<PostingSettings>,
+    CsrfForm(form): CsrfForm<PostForm>,
+) -> Result<Redirect, PostingError> {
+    form.validate()?;
+    let topic = Topic::query(&db, Some(&auth), id).await?;
+    let post = Post::insert(&db, &auth, settings, &topic, &form.body).await?;
+    Ok(Redirect::to(&format!(
+        "{}#post-{}",
+        page_url(&post),
+        post.post_number
+    )))
+}
+
+/// Form to start a new topic
+#[instrument(skip_all)]
+pub async fn new_get_handler(
+    auth: UserAuth,
+    csrf: CsrfToken,
+) -> Result<impl IntoResponse, TopicError> {
+    if !auth.can_post() {
+        return Err(TopicError::Forbidden(format!(
+            "user {} ({:?}) cannot post topic",
+            auth.id, auth.role
+        )));
+    }
+    Ok(Html(
+        html! {
+            a href="/" { "Index of Reforum" }
+            h1 { "New topic" }
+            form method="post" action="/topics/new" {
+                (csrf)
+                div {
+                    label for="title" { "Title" }
+                    br;
+                    input type="text" id="title" name="title" size="80" maxlength="200" required;
+                }
+                (body_textarea())
+                div {
+                    input type="checkbox" id="hidden" name="hidden";
+                    label for="hidden" { "Only visible to me and moderators" }
+                }
+                button type="submit" { "Create topic" }
+            }
+        }
+        .0,
+    ))
+}
+
+/// Start a new topic
+#[instrument(skip_all)]
+pub async fn new_post_handler(
+    auth: UserAuth,
+    Extension(db): Extension<Database>,
+    Extension(settings): Extension<PostingSettings>,
+    CsrfForm(form): CsrfForm<NewTopicForm>,
+) -> Result<Redirect, PostingError> {
+    form.validate()?;
+    let (_, post) = Topic::insert_topic(
+        &db,
+        &auth,
+        settings,
+        form.title.trim(),
+        form.hidden.is_none(),
+        &form.body,
+    )
+    .await?;
+    Ok(Redirect::to(&format!(
+        "{}#post-{}",
+        page_url(&post),
+        post.post_number
+    )))
+}
+
+/// Form to edit the title of a topic
+#[instrument(skip_all, fields(id=id))]
+pub async fn edit_get_handler(
+    Path(id): Path<i64>,
+    auth: UserAuth,
+    csrf: CsrfToken,
+    Extension(db): Extension<Database>,
+) -> Result<impl IntoResponse, TopicError> {
+    let topic = Topic::query(&db, Some(&auth), id).await?;
+    if !auth.can_edit(topic.author_user_id) {
+        return Err(TopicError::Forbidden(format!(
+            "user {} cannot edit topic {}",
+            auth.id, id
+        )));
+    }
+    Ok(Html(
+        html! {
+            a href=(format!("/topics/{}", topic.id)) { (topic.title) }
+            h1 { "Edit topic" }
+            form method="post" action=(format!("/topics/{}/edit", topic.id)) {
+                (csrf)
+                div {
+                    label for="title" { "Title" }
+                    br;
+                    input type="text" id="title" name="title" size="80" maxlength="200" required value=(topic.title);
+                }
+                button type="submit" { "Save" }
+            }
+        }
+        .0,
+    ))
+}
+
+/// Edit the title of a topic
+#[instrument(skip_all, fields(id=id))]
+pub async fn edit_post_handler(
+    Path(id): Path<i64>,
+    auth: UserAuth,
+    Extension(db): Extension<Database>,
+    CsrfForm(form): CsrfForm<TitleForm>,
+) -> Result<Redirect, TopicError> {
+    form.validate()?;
+    let topic = Topic::query(&db, Some(&auth), id).await?;
+    let topic = topic.edit_title(&db, &auth, form.title.trim()).await?;
+    Ok(Redirect::to(&format!("/topics/{}", topic.id)))
+}
+
+/// Title history of a topic
+#[instrument(skip_all, fields(id=id))]
+pub async fn revisions_handler(
+    Path(id): Path<i64>,
+    auth: Option<UserAuth>,
+    Extension(db): Extension<Database>,
+) -> Result<impl IntoResponse, TopicError> {
+    let topic = Topic::query(&db, auth.as_ref(), id).await?;
+    let (revisions, editors) = db
+        .interact(move |conn| -> Result<_, rusqlite::Error> {
+            let revisions = Revision::query_by_topic_id(conn, id)?;
+            let editors = User::query_many(conn, revisions.iter().map(|r| r.edited_by))?;
+            Ok((revisions, editors))
+        })
+        .await??;
+    Ok(Html(
+        html! {
+            a href=(format!("/topics/{}", topic.id)) { (topic.title) }
+            h1 { "Title history" }
+            (history_markup(&revisions, &topic.title, &editors))
+        }
+        .0,
+    ))
+}
+
+/// Soft-delete a topic
+#[instrument(skip_all, fields(id=id))]
+pub async fn delete_handler(
+    Path(id): Path<i64>,
+    auth: UserAuth,
+    Extension(db): Extension<Database>,
+    _: CsrfCheck,
+) -> Result<Redirect, TopicError> {
+    let topic = Topic::query(&db, Some(&auth), id).await?;
+    topic.delete(&db, &auth).await?;
+    if auth.is_moderator() || auth.is_admin() {
+        Ok(Redirect::to(&format!("/topics/{}", id)))
+    } else {
+        Ok(Redirect::to("/"))
+    }
+}
+
+/// Restore a soft-deleted topic
+#[instrument(skip_all, fields(id=id))]
+pub async fn restore_handler(
+    Path(id): Path<i64>,
+    auth: UserAuth,
+    Extension(db): Extension<Database>,
+    _: CsrfCheck,
+) -> Result<Redirect, TopicError> {
+    let topic = Topic::query(&db, Some(&auth), id).await?;
+    topic.restore(&db, &auth).await?;
+    Ok(Redirect::to(&format!("/topics/{}", id)))
 }
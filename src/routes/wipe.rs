@@ -0,0 +1,198 @@
+use axum::{
+    extract::Path,
+    response::{Html, IntoResponse, Redirect},
+    Extension,
+};
+use axum_sessions::SessionHandle;
+use maud::html;
+use serde::Deserialize;
+use tracing::instrument;
+
+use crate::{
+    auth::{
+        csrf::{CsrfCheck, CsrfForm, CsrfToken},
+        extractor::UserAuth,
+    },
+    configuration::AccountSettings,
+    model::{
+        database::Database,
+        user::User,
+        wipe::{WipeError, WipeOutcome, WipeRequest},
+    },
+};
+
+#[derive(Deserialize)]
+pub struct WipeForm {
+    /// Must repeat the username of the account
+    confirm_username: String,
+}
+
+/// Page to request wiping the viewer's own account
+#[instrument(skip_all)]
+pub async fn request_get_handler(
+    auth: UserAuth,
+    csrf: CsrfToken,
+    Extension(settings): Extension<AccountSettings>,
+    Extension(db): Extension<Database>,
+) -> Result<impl IntoResponse, WipeError> {
+    let pending = WipeRequest::query_by_user_id(&db, auth.id).await?;
+    let needs_approval = auth.is_moderator() || settings.wipe_requires_approval;
+    Ok(Html(
+        html! {
+            a href="/" { "Index of Reforum" }
+            h1 { "Wipe account" }
+            @if let Some(request) = pending {
+                p {
+                    "You requested to wipe your account on "
+                    (request.created_at.format("%Y-%m-%d %H:%M"))
+                    @if request.requires_admin {
+                        ". It waits for the admin's approval."
+                    } @else {
+                        ". It waits for a moderator's approval."
+                    }
+                }
+                form method="post" action="/account/wipe/withdraw" {
+                    (csrf)
+                    button type="submit" { "Withdraw request" }
+                }
+            } @else {
+                p {
+                    "Every topic, post and reply you wrote will be deleted, and you will not be able to log in again."
+                    @if needs_approval {
+                        " The wipe happens once your request is approved."
+                    }
+                }
+                form method="post" action="/account/wipe" {
+                    (csrf)
+                    div {
+                        label for="confirm_username" { "Type your username to confirm" }
+                        br;
+                        input type="text" id="confirm_username" name="confirm_username" required;
+                    }
+                    button type="submit" { "Wipe my account" }
+                }
+            }
+        }
+        .0,
+    ))
+}
+
+/// Request wiping the viewer's own account
+#[instrument(skip_all)]
+pub async fn request_post_handler(
+    auth: UserAuth,
+    Extension(session): Extension<SessionHandle>,
+    Extension(settings): Extension<AccountSettings>,
+    Extension(db): Extension<Database>,
+    CsrfForm(form): CsrfForm<WipeForm>,
+) -> Result<Redirect, WipeError> {
+    let user_id = auth.id;
+    let user = db
+        .interact(move |conn| User::query(conn, user_id))
+        .await??;
+    if user.map(|u| u.username) != Some(form.confirm_username) {
+        return Err(WipeError::Forbidden(
+            "username does not match the account".to_owned(),
+        ));
+    }
+    match WipeRequest::request(&db, &auth, settings).await? {
+        WipeOutcome::Wiped => {
+            session.write().await.destroy();
+            Ok(Redirect::to("/"))
+        }
+        WipeOutcome::Pending(_) => Ok(Redirect::to("/account/wipe")),
+    }
+}
+
+/// Withdraw the viewer's pending wipe request
+#[instrument(skip_all)]
+pub async fn withdraw_handler(
+    auth: UserAuth,
+    Extension(db): Extension<Database>,
+    _: CsrfCheck,
+) -> Result<Redirect, WipeError> {
+    if let Some(request) = WipeRequest::query_by_user_id(&db, auth.id).await? {
+        request.reject(&db, &auth).await?;
+    }
+    Ok(Redirect::to("/account/wipe"))
+}
+
+/// Wipe requests awaiting the viewer's approval
+#[instrument(skip_all)]
+pub async fn queue_handler(
+    auth: UserAuth,
+    csrf: CsrfToken,
+    Extension(db): Extension<Database>,
+) -> Result<impl IntoResponse, WipeError> {
+    let requests = WipeRequest::pending(&db, &auth).await?;
+    Ok(Html(
+        html! {
+            a href="/" { "Index of Reforum" }
+            h1 { "Wipe requests" }
+            @if requests.is_empty() {
+                p { "No pending requests." }
+            } @else {
+                table {
+                    thead {
+                        tr {
+                            th { "User" }
+                            th { "Requested" }
+                            th {}
+                        }
+                    }
+                    tbody {
+                        @for (request, username) in &requests {
+                            tr {
+                                td {
+                                    (username) " (UID " (request.user_id) ")"
+                                    @if request.requires_admin {
+                                        " [moderator]"
+                                    }
+                                }
+                                td { (request.created_at.format("%Y-%m-%d %H:%M")) }
+                                td {
+                                    form method="post" action=(format!("/wipe-requests/{}/approve", request.id)) style="display: inline" {
+                                        (csrf)
+                                        button type="submit" { "Approve and wipe" }
+                                    }
+                                    " "
+                                    form method="post" action=(format!("/wipe-requests/{}/reject", request.id)) style="display: inline" {
+                                        (csrf)
+                                        button type="submit" { "Reject" }
+                                    }
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        .0,
+    ))
+}
+
+/// Approve a wipe request, wiping the account
+#[instrument(skip_all, fields(id=id))]
+pub async fn approve_handler(
+    Path(id): Path<i64>,
+    auth: UserAuth,
+    Extension(db): Extension<Database>,
+    _: CsrfCheck,
+) -> Result<Redirect, WipeError> {
+    let request = WipeRequest::query(&db, id).await?;
+    request.approve(&db, &auth).await?;
+    Ok(Redirect::to("/wipe-requests"))
+}
+
+/// Reject a wipe request
+#[instrument(skip_all, fields(id=id))]
+pub async fn reject_handler(
+    Path(id): Path<i64>,
+    auth: UserAuth,
+    Extension(db): Extension<Database>,
+    _: CsrfCheck,
+) -> Result<Redirect, WipeError> {
+    let request = WipeRequest::query(&db, id).await?;
+    request.reject(&db, &auth).await?;
+    Ok(Redirect::to("/wipe-requests"))
+}
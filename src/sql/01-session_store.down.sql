@@ -0,0 +1,9 @@
+DROP INDEX idx_user_sessions_expires_at;
+DROP TABLE user_sessions;
+
+CREATE TABLE user_sessions(
+    id BLOB PRIMARY KEY,
+    session_user_id INTEGER NOT NULL REFERENCES users(id) ON DELETE CASCADE ON UPDATE CASCADE,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    expires_at TIMESTAMP
+);
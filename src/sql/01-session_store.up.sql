@@ -0,0 +1,13 @@
+-- Sessions are stored by `auth::session_store::SQLiteSessionStore`.
+-- Anonymous sessions have no user, so `session_user_id` is nullable.
+DROP TABLE user_sessions;
+
+CREATE TABLE user_sessions(
+    id TEXT PRIMARY KEY,
+    session_user_id INTEGER REFERENCES users(id) ON DELETE CASCADE ON UPDATE CASCADE,
+    session TEXT NOT NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    expires_at TIMESTAMP
+);
+
+CREATE INDEX idx_user_sessions_expires_at ON user_sessions(expires_at);
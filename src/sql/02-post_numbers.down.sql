@@ -0,0 +1,28 @@
+DROP INDEX idx_posts_topic_id_post_number;
+DROP TRIGGER tr_posts_after_insert;
+
+CREATE TRIGGER tr_posts_after_insert BEFORE
+INSERT
+    ON posts BEGIN -- Set post number
+UPDATE
+    posts
+SET
+    post_number = (
+        SELECT
+            number_posts
+        FROM
+            topics
+        WHERE
+            topics.id = NEW.topic_id
+    )
+WHERE
+    rowid = NEW.rowid;
+
+UPDATE
+    topics
+SET
+    number_posts = number_posts + 1
+WHERE
+    id = NEW.topic_id;
+
+END;
@@ -0,0 +1,41 @@
+-- `tr_posts_after_insert` ran BEFORE INSERT, so it could not update the new
+-- row and `post_number` stayed NULL. Inserts now set `post_number` from
+-- `topics.number_posts` themselves, and the trigger only keeps count.
+DROP TRIGGER tr_posts_after_insert;
+
+CREATE TRIGGER tr_posts_after_insert
+AFTER
+INSERT
+    ON posts BEGIN
+UPDATE
+    topics
+SET
+    number_posts = number_posts + 1
+WHERE
+    id = NEW.topic_id;
+
+END;
+
+-- Number the posts left without one in the order they were made, from 0 in
+-- each topic, as inserts do
+UPDATE
+    posts
+SET
+    post_number = numbered.post_number
+FROM
+    (
+        SELECT
+            id,
+            ROW_NUMBER() OVER (
+                PARTITION BY topic_id
+                ORDER BY
+                    created_at,
+                    id
+            ) - 1 AS post_number
+        FROM
+            posts
+    ) AS numbered
+WHERE
+    posts.id = numbered.id;
+
+CREATE UNIQUE INDEX idx_posts_topic_id_post_number ON posts(topic_id, post_number);
@@ -0,0 +1,15 @@
+-- Each row is a version of a topic title or a post body that has been
+-- replaced by an edit. `edited_by` is who replaced it, and `created_at` when.
+CREATE TABLE revisions(
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    topic_id INTEGER REFERENCES topics(id) ON DELETE CASCADE ON UPDATE CASCADE,
+    post_id INTEGER REFERENCES posts(id) ON DELETE CASCADE ON UPDATE CASCADE,
+    content TEXT NOT NULL,
+    edited_by INTEGER NOT NULL REFERENCES users(id) ON UPDATE CASCADE,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    CHECK ((topic_id IS NULL) <> (post_id IS NULL))
+);
+
+CREATE INDEX idx_revisions_topic_id ON revisions(topic_id);
+
+CREATE INDEX idx_revisions_post_id ON revisions(post_id);
@@ -0,0 +1,23 @@
+DROP TRIGGER tr_posts_after_update_deleted_at;
+
+DROP TRIGGER tr_posts_after_insert;
+
+CREATE TRIGGER tr_posts_after_insert
+AFTER
+INSERT
+    ON posts BEGIN
+UPDATE
+    topics
+SET
+    number_posts = number_posts + 1
+WHERE
+    id = NEW.topic_id;
+
+END;
+
+UPDATE
+    topics
+SET
+    number_posts = next_post_number;
+
+ALTER TABLE topics DROP COLUMN next_post_number;
@@ -0,0 +1,58 @@
+-- `number_posts` counted every post ever made, because it also numbered new
+-- posts. Numbering moves to `next_post_number`, so that `number_posts` can
+-- count only the posts that are not soft-deleted.
+ALTER TABLE topics ADD COLUMN next_post_number INTEGER NOT NULL DEFAULT 0;
+
+UPDATE
+    topics
+SET
+    next_post_number = (
+        SELECT
+            COALESCE(MAX(post_number) + 1, 0)
+        FROM
+            posts
+        WHERE
+            posts.topic_id = topics.id
+    ),
+    number_posts = (
+        SELECT
+            COUNT(*)
+        FROM
+            posts
+        WHERE
+            posts.topic_id = topics.id
+            AND posts.deleted_at IS NULL
+    );
+
+DROP TRIGGER tr_posts_after_insert;
+
+CREATE TRIGGER tr_posts_after_insert
+AFTER
+INSERT
+    ON posts BEGIN
+UPDATE
+    topics
+SET
+    next_post_number = next_post_number + 1,
+    number_posts = number_posts + (NEW.deleted_at IS NULL)
+WHERE
+    id = NEW.topic_id;
+
+END;
+
+CREATE TRIGGER tr_posts_after_update_deleted_at
+AFTER
+UPDATE
+    OF deleted_at ON posts
+    WHEN (OLD.deleted_at IS NULL) <> (NEW.deleted_at IS NULL) BEGIN
+UPDATE
+    topics
+SET
+    number_posts = number_posts + CASE
+        WHEN NEW.deleted_at IS NULL THEN 1
+        ELSE -1
+    END
+WHERE
+    id = NEW.topic_id;
+
+END;
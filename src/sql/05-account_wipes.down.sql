@@ -0,0 +1,3 @@
+DROP TABLE wipe_requests;
+
+ALTER TABLE users DROP COLUMN wiped_at;
@@ -0,0 +1,12 @@
+-- Wiped accounts keep their row, so that revisions, `last_updated_by` and
+-- `past_moderators` still refer to a valid UID, but lose their username,
+-- password and content.
+ALTER TABLE users ADD COLUMN wiped_at TIMESTAMP;
+
+CREATE TABLE wipe_requests(
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    user_id INTEGER NOT NULL UNIQUE REFERENCES users(id) ON DELETE CASCADE ON UPDATE CASCADE,
+    -- Moderators' requests may only be approved by the admin
+    requires_admin BOOLEAN NOT NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
@@ -0,0 +1,3 @@
+ALTER TABLE users DROP COLUMN mute_reason;
+
+ALTER TABLE users DROP COLUMN ban_reason;
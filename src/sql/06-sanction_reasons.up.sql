@@ -0,0 +1,4 @@
+-- Reasons shown to banned and muted users, cleared with the sanction
+ALTER TABLE users ADD COLUMN ban_reason TEXT;
+
+ALTER TABLE users ADD COLUMN mute_reason TEXT;
@@ -0,0 +1,34 @@
+-- Every privileged action, kept even after the content or account it
+-- refers to is gone. Content IDs are therefore not foreign keys.
+-- `moderator_user_id` is NULL for actions run from the command line.
+CREATE TABLE moderation_log(
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    moderator_user_id INTEGER REFERENCES users(id) ON UPDATE CASCADE,
+    action TEXT NOT NULL,
+    target_user_id INTEGER REFERENCES users(id) ON UPDATE CASCADE,
+    topic_id INTEGER,
+    post_id INTEGER,
+    reply_id INTEGER,
+    reason TEXT,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX idx_moderation_log_moderator_user_id ON moderation_log(moderator_user_id);
+
+CREATE INDEX idx_moderation_log_target_user_id ON moderation_log(target_user_id);
+
+CREATE TRIGGER tr_moderation_log_before_update BEFORE
+UPDATE
+    ON moderation_log BEGIN
+SELECT
+    RAISE(ABORT, 'moderation_log is append-only');
+
+END;
+
+CREATE TRIGGER tr_moderation_log_before_delete BEFORE
+DELETE
+    ON moderation_log BEGIN
+SELECT
+    RAISE(ABORT, 'moderation_log is append-only');
+
+END;
@@ -0,0 +1,3 @@
+UPDATE users SET muted_until = '9999-12-31 23:59:59+00:00' WHERE role = 'viewer';
+
+ALTER TABLE users DROP COLUMN role;
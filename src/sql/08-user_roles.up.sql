@@ -0,0 +1,9 @@
+-- Base role of a user. Bans, mutes and the `moderators` table are layered
+-- on top of it, except for admins who cannot be sanctioned.
+ALTER TABLE users ADD COLUMN role TEXT NOT NULL DEFAULT 'author' CHECK (role IN ('author', 'viewer', 'admin'));
+
+UPDATE users SET role = 'admin' WHERE id = 1;
+
+-- Viewers used to be modeled as users muted indefinitely
+UPDATE users SET role = 'viewer', muted_until = NULL, mute_reason = NULL
+WHERE muted_until >= '9999-01-01';
@@ -0,0 +1,19 @@
+-- Failed password guesses, kept for review. `username` is what was typed,
+-- which may not belong to any account.
+CREATE TABLE login_failures(
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    username TEXT NOT NULL,
+    user_id INTEGER REFERENCES users(id) ON DELETE SET NULL ON UPDATE CASCADE,
+    ip TEXT NOT NULL,
+    created_at TIMESTAMP NOT NULL
+);
+
+CREATE INDEX idx_login_failures_username ON login_failures(username, created_at);
+
+CREATE INDEX idx_login_failures_ip ON login_failures(ip, created_at);
+
+-- Failures before the last successful login or the end of the last lockout
+-- no longer count towards backoff and lockout
+ALTER TABLE users ADD COLUMN last_login_at TIMESTAMP;
+
+ALTER TABLE users ADD COLUMN locked_until TIMESTAMP;
@@ -0,0 +1,15 @@
+-- One-time links issued by the admin to let a user choose a new password.
+-- Only the SHA-256 of the token is stored, so that the table alone cannot be
+-- used to take over accounts. `created_by` is NULL for links issued from the
+-- command line.
+CREATE TABLE password_resets(
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    user_id INTEGER NOT NULL REFERENCES users(id) ON DELETE CASCADE ON UPDATE CASCADE,
+    token_hash TEXT NOT NULL UNIQUE,
+    created_by INTEGER REFERENCES users(id) ON DELETE SET NULL ON UPDATE CASCADE,
+    created_at TIMESTAMP NOT NULL,
+    expires_at TIMESTAMP NOT NULL,
+    used_at TIMESTAMP
+);
+
+CREATE INDEX idx_password_resets_user_id ON password_resets(user_id);
@@ -1 +1,75 @@
+use rusqlite::Connection;
+use rusqlite_migration::{Migrations, SchemaVersion, M};
 
+/// A numbered `NN-name.up.sql`/`NN-name.down.sql` pair in this directory
+pub struct MigrationFile {
+    pub number: usize,
+    pub name: &'static str,
+    pub up: &'static str,
+    pub down: Option<&'static str>,
+}
+
+// Generated by `build.rs`
+include!(concat!(env!("OUT_DIR"), "/migrations.rs"));
+
+/// All migrations, in order. Migration `NN` brings the schema to version `NN + 1`.
+pub fn migrations() -> Migrations<'static> {
+    Migrations::new_iter(MIGRATION_FILES.iter().map(|file| {
+        let m = M::up(file.up);
+        match file.down {
+            Some(down) => m.down(down),
+            None => m,
+        }
+    }))
+}
+
+/// Schema version of the database, 0 if no migration has been applied
+pub fn current_version(conn: &Connection) -> Result<usize, rusqlite_migration::Error> {
+    Ok(match migrations().current_version(conn)? {
+        SchemaVersion::NoneSet => 0,
+        SchemaVersion::Inside(v) | SchemaVersion::Outside(v) => v.get(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn legacy_posts_are_numbered() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        // Before `02-post_numbers`, every post was left without a number
+        migrations().to_version(&mut conn, 2).unwrap();
+        conn.execute_batch(
+            r#"
+            INSERT INTO topics(id, author_user_id, title) VALUES (1, 1, 'One'), (2, 1, 'Two');
+            INSERT INTO posts(id, topic_id, author_user_id, body, created_at) VALUES
+                (1, 1, 1, 'a', '2020-01-01 00:00:02'),
+                (2, 2, 1, 'b', '2020-01-01 00:00:00'),
+                (3, 1, 1, 'c', '2020-01-01 00:00:01'),
+                (4, 1, 1, 'd', '2020-01-01 00:00:01');
+            "#,
+        )
+        .unwrap();
+        migrations().to_latest(&mut conn).unwrap();
+
+        let mut stmt = conn
+            .prepare(r#"SELECT id, post_number FROM posts ORDER BY id"#)
+            .unwrap();
+        let numbers = stmt
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))
+            .unwrap()
+            .collect::<Result<Vec<(i64, i64)>, _>>()
+            .unwrap();
+        assert_eq!(numbers, [(1, 2), (2, 0), (3, 0), (4, 1)]);
+
+        let next: i64 = conn
+            .query_row(
+                r#"SELECT next_post_number FROM topics WHERE id = 1"#,
+                [],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(next, 3);
+    }
+}
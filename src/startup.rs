@@ -1,57 +1,127 @@
-use axum::handler::HandlerWithoutStateExt;
-use axum::routing::get;
+use axum::middleware::map_request_with_state;
+use axum::routing::{get, post};
 use axum::{Extension, Router};
-use axum_sessions::{async_session, SameSite, SessionLayer};
-use rand::Rng;
+use axum_sessions::{SameSite, SessionLayer};
+use secrecy::ExposeSecret;
+use std::net::SocketAddr;
 use std::time::Duration;
 
-use rusqlite_migration::{Migrations, M};
 use tower::builder::ServiceBuilder;
 use tower_http::compression::CompressionLayer;
 
 use super::routes::fallback::handler_404;
-use crate::configuration::{get_configuration, SessionCookieName};
+use crate::auth::key_rotation::{rotate_session_cookie, KeyRotation};
+use crate::auth::session_store::SQLiteSessionStore;
+use crate::configuration::Settings;
+use crate::model::database::Database;
 
 use crate::routes::*;
-use crate::telemetry::{init_telemetry, setup_telemetry};
+use crate::sql::migrations;
+use crate::telemetry::setup_telemetry;
 
-pub async fn run() -> color_eyre::Result<()> {
-    init_telemetry();
-    let configuration = get_configuration().expect("Failed to read configuration");
+pub async fn run(configuration: Settings) -> color_eyre::Result<()> {
+    let addr = configuration.address();
+    let db = Database::new(&configuration.database)?;
+    let pragmas = configuration.database.pragmas.clone();
+    db.interact(move |conn| pragmas.verify(conn)).await??;
+    db.interact(|conn| migrations().to_latest(conn)).await??;
 
-    let store = async_session::MemoryStore::new();
+    let store = SQLiteSessionStore::new(db.clone());
+    store.spawn_cleanup_task(Duration::from_secs(60 * 60));
     let session_cookie_name = configuration.session_cookie_name;
-    let secret = rand::thread_rng().gen::<[u8; 128]>();
-    let session_layer = SessionLayer::new(store, &secret)
+    let secrets = configuration.session_secret.secrets()?;
+    let key_rotation = KeyRotation::new(session_cookie_name.0.clone(), &secrets);
+    let session_layer = SessionLayer::new(store, secrets.key.expose_secret())
         .with_cookie_name(session_cookie_name.0)
         .with_same_site_policy(SameSite::Strict)
         .with_http_only(true)
         .with_secure(true)
         .with_session_ttl(Some(Duration::from_secs(60 * 60 * 24 * 30)));
 
-    let addr = format!("{}:{}", configuration.listen, configuration.port).parse()?;
-
-    let migrations = Migrations::new(vec![M::up(include_str!("sql/00-create_tables.up.sql"))
-        .down(include_str!("sql/00-create_tables.down.sql"))]);
-    let mut db = rusqlite::Connection::open(&configuration.database.connection)?;
-    migrations.to_latest(&mut db)?;
-
     // build our application with a route
     let app = Router::new()
         .route("/", get(index::handler))
-        // .route("/login", get(login::get_handler).post(login::post_handler))
-        // .route("/logout", get(logout::handler))
-        // .route(
-        //     "/topics/:id",
-        //     get(topics::get_handler).post(topics::post_handler),
-        // )
+        .route(
+            "/register",
+            get(register::get_handler).post(register::post_handler),
+        )
+        .route("/login", get(login::get_handler).post(login::post_handler))
+        .route("/logout", post(logout::handler))
+        .route(
+            "/topics/new",
+            get(topics::new_get_handler).post(topics::new_post_handler),
+        )
+        .route(
+            "/topics/:id",
+            get(topics::get_handler).post(topics::post_handler),
+        )
+        .route(
+            "/topics/:id/edit",
+            get(topics::edit_get_handler).post(topics::edit_post_handler),
+        )
+        .route("/topics/:id/revisions", get(topics::revisions_handler))
+        .route("/topics/:id/delete", post(topics::delete_handler))
+        .route("/topics/:id/restore", post(topics::restore_handler))
+        .route(
+            "/posts/:id/edit",
+            get(posts::edit_get_handler).post(posts::edit_post_handler),
+        )
+        .route("/posts/:id/revisions", get(posts::revisions_handler))
+        .route("/posts/:id/delete", post(posts::delete_handler))
+        .route("/posts/:id/restore", post(posts::restore_handler))
+        .route("/posts/:id/replies", post(replies::post_handler))
+        .route("/replies/:id/delete", post(replies::delete_handler))
+        .route(
+            "/account/wipe",
+            get(wipe::request_get_handler).post(wipe::request_post_handler),
+        )
+        .route("/account/wipe/withdraw", post(wipe::withdraw_handler))
+        .route(
+            "/account/password",
+            get(password::change_get_handler).post(password::change_post_handler),
+        )
+        .route(
+            "/password-reset/:token",
+            get(password::reset_get_handler).post(password::reset_post_handler),
+        )
+        .route("/wipe-requests", get(wipe::queue_handler))
+        .route("/wipe-requests/:id/approve", post(wipe::approve_handler))
+        .route("/wipe-requests/:id/reject", post(wipe::reject_handler))
+        .route(
+            "/admin/moderators",
+            get(moderators::get_handler).post(moderators::promote_handler),
+        )
+        .route(
+            "/admin/moderators/:user_id/demote",
+            post(moderators::demote_handler),
+        )
+        .route("/admin/log", get(moderation_log::handler))
+        .route("/admin/login-failures", get(login_failures::get_handler))
+        .route(
+            "/admin/login-failures/:user_id/unlock",
+            post(login_failures::unlock_handler),
+        )
+        .route(
+            "/admin/password-resets",
+            get(password::issue_get_handler).post(password::issue_post_handler),
+        )
+        .route(
+            "/moderation/users/:user_id",
+            get(moderation::get_handler).post(moderation::post_handler),
+        )
         .fallback(handler_404);
 
     let app = app.layer(
         ServiceBuilder::new()
+            .layer(map_request_with_state(key_rotation, rotate_session_cookie))
             .layer(session_layer)
             .layer(CompressionLayer::new().gzip(true).deflate(true).br(true))
-            .layer(Extension(configuration.database)),
+            .layer(Extension(db))
+            .layer(Extension(configuration.registration))
+            .layer(Extension(configuration.accounts))
+            .layer(Extension(configuration.login))
+            .layer(Extension(configuration.posting))
+            .layer(Extension(configuration.password_hash)),
     );
 
     let app = setup_telemetry(app);
@@ -60,7 +130,7 @@ pub async fn run() -> color_eyre::Result<()> {
 
     tracing::debug!("listening on {}", addr);
     axum::Server::bind(&addr)
-        .serve(app.into_make_service())
+        .serve(app.into_make_service_with_connect_info::<SocketAddr>())
         .await?;
     Ok(())
 }
@@ -1,6 +1,10 @@
 use std::os::raw::c_int;
 
-use axum::{body::Body, http::Request, Router};
+use axum::{
+    body::Body,
+    http::{Request, Uri},
+    Router,
+};
 use tokio::task::JoinHandle;
 use tower_http::{
     trace::{DefaultOnRequest, DefaultOnResponse, TraceLayer},
@@ -40,12 +44,27 @@ pub fn setup_telemetry(app: Router) -> Router {
                     "request",
                     id = %nanoid::nanoid!(),
                     method = %request.method(),
-                    uri = %request.uri(),
+                    uri = %logged_uri(request.uri()),
                 )
             }),
     )
 }
 
+/// Paths whose remainder is a one-time secret, such as a password reset token
+const SECRET_PATH_PREFIXES: &[&str] = &["/password-reset/"];
+
+/// The request URI as written to the log, with secrets in the path redacted
+fn logged_uri(uri: &Uri) -> String {
+    let path = uri.path();
+    match SECRET_PATH_PREFIXES
+        .iter()
+        .find(|prefix| path.starts_with(*prefix))
+    {
+        Some(prefix) => format!("{}[redacted]", prefix),
+        None => uri.to_string(),
+    }
+}
+
 pub fn spawn_blocking_with_tracing<F, R>(f: F) -> JoinHandle<R>
 where
     F: FnOnce() -> R + Send + 'static,
@@ -54,3 +73,33 @@ where
     let current_span = tracing::Span::current();
     tokio::task::spawn_blocking(move || current_span.in_scope(f))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reset_tokens_are_redacted() {
+        let token = "V1StGXR8_Z5jdHi6B-myTV1StGXR8_Z5";
+        for uri in [
+            format!("/password-reset/{}", token),
+            format!("/password-reset/{}?next=/", token),
+            format!("http://localhost:3000/password-reset/{}", token),
+        ] {
+            let logged = logged_uri(&uri.parse().unwrap());
+            assert!(!logged.contains(token), "{}", logged);
+            assert_eq!(logged, "/password-reset/[redacted]");
+        }
+    }
+
+    #[test]
+    fn other_uris_are_logged_whole() {
+        for uri in [
+            "/topics/1?page=2",
+            "/admin/password-resets",
+            "/password-reset",
+        ] {
+            assert_eq!(logged_uri(&uri.parse().unwrap()), uri);
+        }
+    }
+}